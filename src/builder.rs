@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::data::*;
+use crate::error::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Label(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Local(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FunRef(usize);
+
+struct Scope {
+    label : Label,
+    start : Option<usize>,
+    stack : Vec<Option<Local>>,
+    breaks : Vec<usize>,
+}
+
+pub struct FunBuilder<T> {
+    name : Rc<str>,
+    arity : Option<Arity<T>>,
+    instrs : Vec<Op<T>>,
+    // Note:  Mirrors the locals of the frame at the current instruction.  Locals
+    // pushed by raw ops or GenOps have no handle.
+    stack : Vec<Option<Local>>,
+    scopes : Vec<Scope>,
+    marks : HashMap<Label, Option<usize>>,
+    fixups : Vec<(usize, Label)>,
+    next_label : usize,
+    next_local : usize,
+    error : Option<BuildError>,
+}
+
+impl<T> FunBuilder<T> {
+    pub fn new(name : &str) -> Self {
+        FunBuilder {
+            name: name.into(),
+            arity: None,
+            instrs: vec![],
+            stack: vec![],
+            scopes: vec![],
+            marks: HashMap::new(),
+            fixups: vec![],
+            next_label: 0,
+            next_local: 0,
+            error: None,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn slot(&self, local : Local) -> Option<usize> {
+        self.stack.iter().position(|x| *x == Some(local))
+    }
+
+    // Note:  GenOps can push any number of locals, so the builder assumes they push none.
+    // Use assume_pushed to account for GenOps that do.
+    pub fn emit(&mut self, op : Op<T>) -> &mut Self {
+        match op {
+            Op::Drop(slot) if slot < self.stack.len() => { self.stack.remove(slot); },
+            Op::Swap(a, b) if a < self.stack.len() && b < self.stack.len() => { self.stack.swap(a, b); },
+            Op::Store(_) | Op::StoreGlobal(_) | Op::StoreGlobalSym(_) => { self.stack.pop(); },
+            Op::CallMove(_, ref params) | Op::DynCallMove(ref params) => {
+                let mut slots = params.clone();
+                slots.sort_unstable();
+                slots.dedup();
+                slots.retain(|slot| *slot < self.stack.len());
+                for slot in slots.into_iter().rev() {
+                    self.stack.remove(slot);
+                }
+            },
+            Op::Dup(_) | Op::PushRet | Op::PushLocal(_) | Op::Load(_) | Op::LoadGlobal(_) | Op::LoadGlobalSym(_) | Op::CoExport(_) | Op::TaskSpawn(_, _) | Op::ChanNew => { self.stack.push(None); },
+            _ => { },
+        }
+        self.instrs.push(op);
+        self
+    }
+
+    pub fn assume_pushed(&mut self, count : usize) -> &mut Self {
+        self.stack.extend(std::iter::repeat_n(None, count));
+        self
+    }
+
+    pub fn arity(&mut self, arity : Arity<T>) -> &mut Self {
+        self.arity = Some(arity);
+        self
+    }
+
+    // Note:  Parameters are the first locals of the frame, so they should be declared
+    // before anything is emitted.
+    pub fn param(&mut self) -> Local {
+        self.push_handle()
+    }
+
+    pub fn push_local(&mut self, value : T) -> Local {
+        self.instrs.push(Op::PushLocal(value));
+        self.push_handle()
+    }
+
+    pub fn push_ret(&mut self) -> Local {
+        self.instrs.push(Op::PushRet);
+        self.push_handle()
+    }
+
+    pub fn dup(&mut self, local : Local) -> Local {
+        if let Some(slot) = self.resolve(local) {
+            self.instrs.push(Op::Dup(slot));
+        }
+        self.push_handle()
+    }
+
+    pub fn drop_local(&mut self, local : Local) -> &mut Self {
+        if let Some(slot) = self.resolve(local) {
+            self.emit(Op::Drop(slot));
+        }
+        self
+    }
+
+    // Note:  Moves value into the slot held by target and drops the old value, so
+    // target keeps its handle and position.
+    pub fn assign(&mut self, target : Local, value : Local) -> &mut Self {
+        if let (Some(t), Some(v)) = (self.resolve(target), self.resolve(value)) {
+            self.instrs.push(Op::Swap(t, v));
+            self.instrs.push(Op::Drop(v));
+            self.stack.remove(v);
+        }
+        self
+    }
+
+    pub fn gen_op(&mut self, op_index : usize, params : &[Local]) -> &mut Self {
+        if let Some(params) = self.resolve_all(params) {
+            self.instrs.push(Op::Gen(op_index, params));
+        }
+        self
+    }
+
+    pub fn call(&mut self, fun : FunRef, params : &[Local]) -> &mut Self {
+        if let Some(params) = self.resolve_all(params) {
+            self.instrs.push(Op::Call(fun.0, params));
+        }
+        self
+    }
+
+    // Note:  The moved locals go out of scope and the remaining locals shift down.
+    pub fn call_move(&mut self, fun : FunRef, params : &[Local]) -> &mut Self {
+        if let Some(slots) = self.resolve_all(params) {
+            self.emit(Op::CallMove(fun.0, slots));
+        }
+        self
+    }
+
+    pub fn return_local(&mut self, local : Local) -> &mut Self {
+        if let Some(slot) = self.resolve(local) {
+            self.instrs.push(Op::ReturnLocal(slot));
+        }
+        self
+    }
+
+    pub fn return_locals(&mut self, locals : &[Local]) -> &mut Self {
+        if let Some(slots) = self.resolve_all(locals) {
+            self.instrs.push(Op::ReturnLocals(slots));
+        }
+        self
+    }
+
+    // Note:  Names the locals appended by a call to a function that ends with ReturnLocals.
+    pub fn push_returned(&mut self, count : usize) -> Vec<Local> {
+        (0..count).map(|_| self.push_handle()).collect()
+    }
+
+    pub fn label(&mut self) -> Label {
+        let label = self.new_label();
+        self.marks.insert(label, None);
+        label
+    }
+
+    pub fn place(&mut self, label : Label) -> &mut Self {
+        let ip = self.instrs.len();
+        match self.marks.get_mut(&label) {
+            Some(mark @ None) => { *mark = Some(ip); },
+            Some(Some(_)) => { self.fail(BuildError::LabelAlreadyPlaced(Rc::clone(&self.name), ip)); },
+            None => { self.fail(BuildError::LabelOutOfScope(Rc::clone(&self.name), ip)); },
+        }
+        self
+    }
+
+    pub fn jump(&mut self, label : Label) -> &mut Self {
+        self.goto(label, Op::Jump)
+    }
+
+    pub fn branch(&mut self, label : Label) -> &mut Self {
+        self.goto(label, Op::Branch)
+    }
+
+    pub fn branch_false(&mut self, label : Label) -> &mut Self {
+        self.goto(label, Op::BranchFalse)
+    }
+
+    // Note:  The body is repeated until it breaks.  Breaks go to the instruction after
+    // the loop and continues go to the start of the body.
+    pub fn loop_(&mut self, body : impl FnOnce(&mut Self, Label)) -> &mut Self {
+        let start = self.instrs.len();
+        self.scope(Some(start), |b, label| {
+            body(b, label);
+            b.continue_(label);
+        })
+    }
+
+    // Note:  The condition is expected to leave its result in the branch flag.
+    pub fn while_(&mut self, cond : impl FnOnce(&mut Self), body : impl FnOnce(&mut Self, Label)) -> &mut Self {
+        let start = self.instrs.len();
+        self.scope(Some(start), |b, label| {
+            cond(b);
+            b.break_unless(label);
+            body(b, label);
+            b.continue_(label);
+        })
+    }
+
+    pub fn block(&mut self, body : impl FnOnce(&mut Self, Label)) -> &mut Self {
+        self.scope(None, |b, label| {
+            body(b, label);
+            b.check_depth(label);
+        })
+    }
+
+    pub fn break_(&mut self, label : Label) -> &mut Self {
+        self.exit(label, Op::Jump)
+    }
+
+    pub fn break_if(&mut self, label : Label) -> &mut Self {
+        self.exit(label, Op::Branch)
+    }
+
+    pub fn break_unless(&mut self, label : Label) -> &mut Self {
+        self.exit(label, Op::BranchFalse)
+    }
+
+    pub fn continue_(&mut self, label : Label) -> &mut Self {
+        self.restart(label, Op::Jump)
+    }
+
+    pub fn continue_if(&mut self, label : Label) -> &mut Self {
+        self.restart(label, Op::Branch)
+    }
+
+    pub fn build(mut self) -> Result<FunDef<T>, BuildError> {
+        for (index, label) in std::mem::take(&mut self.fixups) {
+            match self.marks[&label] {
+                Some(target) => { set_target(&mut self.instrs[index], target); },
+                None => { self.fail(BuildError::LabelNotPlaced(Rc::clone(&self.name), index)); },
+            }
+        }
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        // Note:  Reserve enough slots for every Store and Load that was emitted.
+        let slots = self.instrs.iter().filter_map(|op| match op {
+            Op::Store(slot) | Op::Load(slot) => Some(slot + 1),
+            _ => None,
+        }).max().unwrap_or(0);
+        let fun = Fun { name: self.name, instrs: self.instrs };
+        fun.verify().map_err(BuildError::Invalid)?;
+        Ok(FunDef { fun, arity: self.arity, slots })
+    }
+
+    fn push_handle(&mut self) -> Local {
+        let local = Local(self.next_local);
+        self.next_local += 1;
+        self.stack.push(Some(local));
+        local
+    }
+
+    fn resolve(&mut self, local : Local) -> Option<usize> {
+        let slot = self.slot(local);
+        if slot.is_none() {
+            self.fail(BuildError::LocalOutOfScope(Rc::clone(&self.name), self.instrs.len()));
+        }
+        slot
+    }
+
+    fn resolve_all(&mut self, locals : &[Local]) -> Option<Vec<usize>> {
+        locals.iter().map(|local| self.resolve(*local)).collect()
+    }
+
+    fn new_label(&mut self) -> Label {
+        let label = Label(self.next_label);
+        self.next_label += 1;
+        label
+    }
+
+    fn goto(&mut self, label : Label, op : fn(usize) -> Op<T>) -> &mut Self {
+        if self.marks.contains_key(&label) {
+            self.fixups.push((self.instrs.len(), label));
+            self.instrs.push(op(0));
+        }
+        else {
+            self.fail(BuildError::LabelOutOfScope(Rc::clone(&self.name), self.instrs.len()));
+        }
+        self
+    }
+
+    fn scope(&mut self, start : Option<usize>, body : impl FnOnce(&mut Self, Label)) -> &mut Self {
+        let label = self.new_label();
+        self.scopes.push(Scope { label, start, stack: self.stack.clone(), breaks: vec![] });
+
+        body(self, label);
+
+        let scope = self.scopes.pop().unwrap();
+        let end = self.instrs.len();
+        for index in scope.breaks {
+            set_target(&mut self.instrs[index], end);
+        }
+        self.stack = scope.stack;
+        self
+    }
+
+    fn exit(&mut self, label : Label, op : fn(usize) -> Op<T>) -> &mut Self {
+        if self.check_depth(label) {
+            let index = self.instrs.len();
+            self.instrs.push(op(0));
+            self.find(label).unwrap().breaks.push(index);
+        }
+        self
+    }
+
+    fn restart(&mut self, label : Label, op : fn(usize) -> Op<T>) -> &mut Self {
+        if self.check_depth(label) {
+            match self.find(label).unwrap().start {
+                Some(start) => { self.instrs.push(op(start)); },
+                None => { self.fail(BuildError::ContinueOutsideLoop(Rc::clone(&self.name), self.instrs.len())); },
+            }
+        }
+        self
+    }
+
+    fn check_depth(&mut self, label : Label) -> bool {
+        let ip = self.instrs.len();
+        let depth = self.stack.len();
+        match self.find(label).map(|scope| scope.stack.len()) {
+            Some(expected) if expected == depth => true,
+            Some(expected) => {
+                self.fail(BuildError::LocalsDepthMismatch(Rc::clone(&self.name), ip, expected, depth));
+                false
+            },
+            None => {
+                self.fail(BuildError::LabelOutOfScope(Rc::clone(&self.name), ip));
+                false
+            },
+        }
+    }
+
+    fn find(&mut self, label : Label) -> Option<&mut Scope> {
+        self.scopes.iter_mut().rev().find(|scope| scope.label == label)
+    }
+
+    fn fail(&mut self, error : BuildError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+}
+
+pub struct ProgramBuilder<T> {
+    names : Vec<Rc<str>>,
+    funs : Vec<Option<FunDef<T>>>,
+}
+
+impl<T> Default for ProgramBuilder<T> {
+    fn default() -> Self {
+        ProgramBuilder { names: vec![], funs: vec![] }
+    }
+}
+
+impl<T> ProgramBuilder<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Note:  Declaring a function reserves its index so that it can be called before
+    // it is defined.
+    pub fn declare(&mut self, name : &str) -> FunRef {
+        self.names.push(name.into());
+        self.funs.push(None);
+        FunRef(self.funs.len() - 1)
+    }
+
+    pub fn define(&mut self, fun : FunRef, builder : FunBuilder<T>) -> Result<(), BuildError> {
+        let mut built = builder.build()?;
+        built.fun.name = Rc::clone(&self.names[fun.0]);
+        self.funs[fun.0] = Some(built);
+        Ok(())
+    }
+
+    pub fn build(self) -> Result<Vec<FunDef<T>>, BuildError> {
+        self.funs.into_iter().zip(self.names).map(|(fun, name)| fun.ok_or(BuildError::UndefinedFun(name))).collect()
+    }
+}
+
+fn set_target<T>(op : &mut Op<T>, target : usize) {
+    match op {
+        Op::Jump(t) | Op::Branch(t) | Op::BranchFalse(t) => { *t = target; },
+        _ => unreachable!(),
+    }
+}
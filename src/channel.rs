@@ -0,0 +1,26 @@
+use std::collections::VecDeque;
+
+use crate::*;
+use crate::data::*;
+use crate::scheduler::*;
+
+pub(crate) struct Channel<T> {
+    pub(crate) queue : VecDeque<T>,
+    pub(crate) closed : bool,
+}
+
+impl<T> Channel<T> {
+    pub(crate) fn new() -> Self {
+        Channel { queue: VecDeque::new(), closed: false }
+    }
+}
+
+impl<T, S> Vm<T, S> {
+    pub(crate) fn wake_receivers(&mut self, channel : usize) {
+        for task in self.tasks.iter_mut() {
+            if matches!(task.state, TaskState::Receiving(handle) if handle == Handle::Channel(channel)) {
+                task.state = TaskState::Ready;
+            }
+        }
+    }
+}
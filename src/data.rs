@@ -1,24 +1,119 @@
 
+use std::future::Future;
+use std::pin::Pin;
 use std::rc::Rc;
 
+use crate::error::*;
+
+#[derive(Debug)]
 pub enum Op<T> {
     Gen(usize, Vec<usize>),
     Call(usize, Vec<usize>),
+    CallMove(usize, Vec<usize>),
+    CallSym(Rc<str>, Vec<usize>),
     ReturnLocal(usize), 
+    ReturnLocals(Vec<usize>),
     Return,
     Branch(usize),
+    Switch(Vec<usize>, usize),
+    SwitchLocal(usize, Vec<usize>, usize),
+    Jump(usize),
+    BranchFalse(usize),
+    SetBranch,
+    ClearBranch,
+    NotBranch,
     DynCall(Vec<usize>),
+    DynCallMove(Vec<usize>),
     Drop(usize),
     Dup(usize),
     Swap(usize, usize),
     PushRet,
     PushLocal(T),
+    Store(usize),
+    Load(usize),
+    LoadGlobal(usize),
+    StoreGlobal(usize),
+    LoadGlobalSym(Rc<str>),
+    StoreGlobalSym(Rc<str>),
     CoYield(usize),
     CoFinish,
+    CoSpawn(usize, Vec<usize>),
+    CoIsFinished(usize),
     CoResume(usize),
+    CoResumeWith(usize, usize),
     CoDrop(usize),
     CoDup(usize), 
     CoSwap(usize, usize),
+    CoOnCancel(usize),
+    CoCancel(usize),
+    CoExport(usize),
+    CoImport(usize),
+    CoRelease(usize),
+    TaskSpawn(usize, Vec<usize>),
+    TaskYield,
+    TaskWait(usize),
+    TaskJoin(usize),
+    ChanNew,
+    ChanSend(usize, usize),
+    ChanRecv(usize),
+    ChanClose(usize),
+}
+
+impl<T> Op<T> {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Gen(..) => "Gen",
+            Op::Call(..) => "Call",
+            Op::CallMove(..) => "CallMove",
+            Op::CallSym(..) => "CallSym",
+            Op::ReturnLocal(..) => "ReturnLocal",
+            Op::ReturnLocals(..) => "ReturnLocals",
+            Op::Return => "Return",
+            Op::Branch(..) => "Branch",
+            Op::Switch(..) => "Switch",
+            Op::SwitchLocal(..) => "SwitchLocal",
+            Op::Jump(..) => "Jump",
+            Op::BranchFalse(..) => "BranchFalse",
+            Op::SetBranch => "SetBranch",
+            Op::ClearBranch => "ClearBranch",
+            Op::NotBranch => "NotBranch",
+            Op::DynCall(..) => "DynCall",
+            Op::DynCallMove(..) => "DynCallMove",
+            Op::Drop(..) => "Drop",
+            Op::Dup(..) => "Dup",
+            Op::Swap(..) => "Swap",
+            Op::PushRet => "PushRet",
+            Op::PushLocal(..) => "PushLocal",
+            Op::Store(..) => "Store",
+            Op::Load(..) => "Load",
+            Op::LoadGlobal(..) => "LoadGlobal",
+            Op::StoreGlobal(..) => "StoreGlobal",
+            Op::LoadGlobalSym(..) => "LoadGlobalSym",
+            Op::StoreGlobalSym(..) => "StoreGlobalSym",
+            Op::CoYield(..) => "CoYield",
+            Op::CoFinish => "CoFinish",
+            Op::CoSpawn(..) => "CoSpawn",
+            Op::CoIsFinished(..) => "CoIsFinished",
+            Op::CoResume(..) => "CoResume",
+            Op::CoResumeWith(..) => "CoResumeWith",
+            Op::CoDrop(..) => "CoDrop",
+            Op::CoDup(..) => "CoDup",
+            Op::CoSwap(..) => "CoSwap",
+            Op::CoOnCancel(..) => "CoOnCancel",
+            Op::CoCancel(..) => "CoCancel",
+            Op::CoExport(..) => "CoExport",
+            Op::CoImport(..) => "CoImport",
+            Op::CoRelease(..) => "CoRelease",
+            Op::TaskSpawn(..) => "TaskSpawn",
+            Op::TaskYield => "TaskYield",
+            Op::TaskWait(..) => "TaskWait",
+            Op::TaskJoin(..) => "TaskJoin",
+            Op::ChanNew => "ChanNew",
+            Op::ChanSend(..) => "ChanSend",
+            Op::ChanRecv(..) => "ChanRecv",
+            Op::ChanClose(..) => "ChanClose",
+        }
+    }
 }
 
 pub struct Fun<T> {
@@ -26,28 +121,183 @@ pub struct Fun<T> {
     pub instrs : Vec<Op<T>>,
 }
 
+// Note:  A function along with how its frame is laid out.  A plain Fun converts into
+// a FunDef with no fixed slots and no arity, so any params are accepted.
+pub struct FunDef<T> {
+    pub fun : Fun<T>,
+    pub arity : Option<Arity<T>>,
+    // Note:  Number of fixed local slots the frame reserves for Store and Load.  Unlike
+    // the stack style locals, a slot keeps its index no matter what is dropped.
+    pub slots : usize,
+}
+
+impl<T> From<Fun<T>> for FunDef<T> {
+    fn from(fun : Fun<T>) -> Self {
+        FunDef { fun, arity: None, slots: 0 }
+    }
+}
+
+impl<T> FunDef<T> {
+    pub fn with_slots(mut self, slots : usize) -> Self {
+        self.slots = slots;
+        self
+    }
+
+    pub fn with_arity(mut self, arity : Arity<T>) -> Self {
+        self.arity = Some(arity);
+        self
+    }
+}
+
+// Note:  Params after the required ones take their value from defaults when they are not
+// given.  With rest set, any params beyond the defaults are kept as extra locals.
+pub struct Arity<T> {
+    pub required : usize,
+    pub defaults : Vec<T>,
+    pub rest : bool,
+}
+
+impl<T> Fun<T> {
+    pub fn new(name : &str, instrs : Vec<Op<T>>) -> Self {
+        Fun { name: name.into(), instrs }
+    }
+
+    pub fn with_slots(self, slots : usize) -> FunDef<T> {
+        FunDef::from(self).with_slots(slots)
+    }
+
+    pub fn with_arity(self, arity : Arity<T>) -> FunDef<T> {
+        FunDef::from(self).with_arity(arity)
+    }
+
+    pub fn verify(&self) -> Result<(), VmError> {
+        for (ip, instr) in self.instrs.iter().enumerate() {
+            let targets = match instr {
+                Op::Branch(target) | Op::BranchFalse(target) | Op::Jump(target) | Op::CoOnCancel(target) => std::slice::from_ref(target),
+                Op::Switch(targets, default) | Op::SwitchLocal(_, targets, default) => {
+                    if *default >= self.instrs.len() {
+                        return Err(VmError::BranchTargetOutOfRange(*default, vec![(Rc::clone(&self.name), ip)].into()));
+                    }
+                    &targets[..]
+                },
+                _ => &[],
+            };
+            for target in targets {
+                if *target >= self.instrs.len() {
+                    return Err(VmError::BranchTargetOutOfRange(*target, vec![(Rc::clone(&self.name), ip)].into()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub type DropHook<T, S> = fn(globals : &mut Vec<S>, frame : &Frame<T>);
+
 pub struct VmEnv<'a, T, S> {
     pub globals: &'a mut Vec<S>,
     pub frames : &'a mut Vec<Frame<T>>,
     pub current : &'a mut Frame<T>,
 }
 
+pub type GenOpResult<T> = Result<Option<T>, Box<dyn std::error::Error>>;
+pub type GenFuture<T> = Pin<Box<dyn Future<Output = GenOpResult<T>>>>;
+
 pub enum GenOp<T, S> {
-    Vm { name : Rc<str>, op : for<'a> fn(vm : VmEnv<'a, T, S>, params : &[usize]) -> Result<Option<T>, Box<dyn std::error::Error>> },
-    Global { name : Rc<str>, op : fn(globals : &mut Vec<S>, params : &[usize]) -> Result<Option<T>, Box<dyn std::error::Error>> },
-    Local { name : Rc<str>, op : fn(locals : &mut Vec<T>, params : &[usize]) -> Result<Option<T>, Box<dyn std::error::Error>> },
-    Frame { name : Rc<str>, op : fn(frame : &mut Frame<T>, params : &[usize]) -> Result<Option<T>, Box<dyn std::error::Error>> },
+    Vm { name : Rc<str>, op : for<'a> fn(vm : VmEnv<'a, T, S>, params : &[usize]) -> GenOpResult<T> },
+    Global { name : Rc<str>, op : fn(globals : &mut Vec<S>, params : &[usize]) -> GenOpResult<T> },
+    Local { name : Rc<str>, op : fn(locals : &mut Vec<T>, params : &[usize]) -> GenOpResult<T> },
+    Frame { name : Rc<str>, op : fn(frame : &mut Frame<T>, params : &[usize]) -> GenOpResult<T> },
+    Async { name : Rc<str>, op : fn(locals : &mut Vec<T>, params : &[usize]) -> GenFuture<T> },
+}
+
+impl<T, S> GenOp<T, S> {
+    pub fn name(&self) -> &Rc<str> {
+        match self {
+            GenOp::Vm { name, .. } 
+            | GenOp::Global { name, .. } 
+            | GenOp::Local { name, .. } 
+            | GenOp::Frame { name, .. } 
+            | GenOp::Async { name, .. } => name,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct Frame<T> {
     pub (crate) fun_id : usize,
+    pub (crate) fun_version : usize,
     pub (crate) ip : usize,
     pub (crate) ret : Option<T>,
     pub branch : bool,
     pub dyn_call : Option<usize>,
+    pub select : Option<usize>,
     pub locals : Vec<T>,
+    pub argc : usize,
+    pub slots : Vec<Option<T>>,
     pub coroutines : Vec<Coroutine<T>>,
+    pub (crate) cleanup : Option<usize>,
+    pub (crate) cancelling : bool,
+}
+
+impl<T> Frame<T> {
+    pub (crate) fn new(fun_id : usize, fun_version : usize, slots : usize, locals : Vec<T>) -> Self {
+        let slots = std::iter::repeat_with(|| None).take(slots).collect();
+        let argc = locals.len();
+        Frame { fun_id, fun_version, ip: 0, ret: None, branch: false, dyn_call: None, select: None, locals, argc, slots, coroutines: vec![], cleanup: None, cancelling: false }
+    }
+
+    pub fn fun_id(&self) -> usize {
+        self.fun_id
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub (crate) fn clone_with(&self, clone : fn(&T) -> T) -> Self {
+        Frame {
+            fun_id: self.fun_id,
+            fun_version: self.fun_version,
+            ip: self.ip,
+            ret: self.ret.as_ref().map(clone),
+            branch: self.branch,
+            dyn_call: self.dyn_call,
+            select: self.select,
+            locals: self.locals.iter().map(clone).collect(),
+            argc: self.argc,
+            slots: self.slots.iter().map(|slot| slot.as_ref().map(clone)).collect(),
+            coroutines: self.coroutines.iter().map(|coroutine| coroutine.clone_with(clone)).collect(),
+            cleanup: self.cleanup,
+            cancelling: self.cancelling,
+        }
+    }
+}
+
+// Note:  Each kind of handle indexes its own table, so a handle is only accepted
+// by the ops for its kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Handle {
+    Coroutine(usize),
+    Task(usize),
+    Channel(usize),
+}
+
+pub trait HandleValue {
+    fn from_handle(handle : Handle) -> Self;
+    fn to_handle(&self) -> Option<Handle>;
+}
+
+// Note:  Lets SwitchLocal read its selection out of a local.  Values that are not a
+// selection go to the default target.
+pub trait SelectValue {
+    fn to_select(&self) -> Option<usize>;
+}
+
+impl SelectValue for usize {
+    fn to_select(&self) -> Option<usize> {
+        Some(*self)
+    }
 }
 
 #[derive(Clone)]
@@ -58,10 +308,15 @@ pub enum Coroutine<T> {
 }
 
 impl<T> Coroutine<T> {
-    pub fn is_alive(&self) -> bool {
-        match self { 
-            Coroutine::Active(_) | Coroutine::Running => true,
-            _ => false,
+    pub (crate) fn clone_with(&self, clone : fn(&T) -> T) -> Self {
+        match self {
+            Coroutine::Active(frame) => Coroutine::Active(frame.clone_with(clone)),
+            Coroutine::Running => Coroutine::Running,
+            Coroutine::Finished => Coroutine::Finished,
         }
     }
+
+    pub fn is_alive(&self) -> bool {
+        matches!(self, Coroutine::Active(_) | Coroutine::Running)
+    }
 }
\ No newline at end of file
@@ -3,49 +3,373 @@ use std::rc::Rc;
 
 pub type StackTrace = Vec<(Rc<str>, usize)>;
 
+// Note:  The op and locals describe the top frame when the error was raised.  The op is
+// only its name and the locals are left out unless debug formatting is enabled on the vm.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorContext {
+    pub trace : StackTrace,
+    pub op : Option<String>,
+    pub locals : Option<Vec<String>>,
+}
+
+impl From<StackTrace> for ErrorContext {
+    fn from(trace : StackTrace) -> Self {
+        ErrorContext { trace, op: None, locals: None }
+    }
+}
+
+// Note:  A comparable view of a VmError for tests and logs.  GenOpError keeps only the
+// message of its inner error, and the context keeps only the trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorSummary {
+    pub kind : ErrorKind,
+    pub trace : StackTrace,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    FunDoesNotExist(usize),
+    DynFunDoesNotExist,
+    InstrPointerOutOfRange(usize),
+    GenOpDoesNotExist(usize),
+    AccessMissingReturn,
+    AccessMissingLocal(usize),
+    GenOpError(Rc<str>, String),
+    TopLevelYield(usize),
+    AccessMissingCoroutine(usize),
+    ResumeFinishedCoroutine(usize),
+    UnlinkedSymbol(Rc<str>),
+    GenOpArityMismatch(Rc<str>, usize, usize),
+    AsyncGenOpInSyncRun(Rc<str>),
+    HandlesNotEnabled,
+    LocalIsNotHandle(usize),
+    AccessMissingHandle(usize),
+    TaskOpOutsideScheduler,
+    JoinFailedTask(usize),
+    SendOnClosedChannel(usize),
+    RecvOnEmptyChannel(usize),
+    BranchTargetOutOfRange(usize),
+    AccessMissingSlot(usize),
+    AccessEmptySlot(usize),
+    TopLevelMultiReturn(usize),
+    ArityMismatch(Rc<str>, usize, usize),
+    CloneNotEnabled,
+    GlobalsNotEnabled,
+    AccessMissingGlobal(usize),
+    GlobalDoesNotExist(Rc<str>),
+    SelectNotEnabled,
+    DuplicateMoveParam(usize),
+    DuplicateFunName(Rc<str>),
+}
+
 #[derive(Debug)]
 pub enum VmError {
-    FunDoesNotExist(usize, StackTrace),
-    DynFunDoesNotExist(StackTrace),
-    InstrPointerOutOfRange(usize, StackTrace),
-    GenOpDoesNotExist(usize, StackTrace),
-    AccessMissingReturn(StackTrace),
-    AccessMissingLocal(usize, StackTrace),
-    GenOpError(Rc<str>, Box<dyn std::error::Error>, StackTrace),
-    TopLevelYield(usize),
-    AccessMissingCoroutine(usize, StackTrace),
-    ResumeFinishedCoroutine(usize, StackTrace),
+    FunDoesNotExist(usize, ErrorContext),
+    DynFunDoesNotExist(ErrorContext),
+    InstrPointerOutOfRange(usize, ErrorContext),
+    GenOpDoesNotExist(usize, ErrorContext),
+    AccessMissingReturn(ErrorContext),
+    AccessMissingLocal(usize, ErrorContext),
+    GenOpError(Rc<str>, Box<dyn std::error::Error>, ErrorContext),
+    TopLevelYield(usize, ErrorContext),
+    AccessMissingCoroutine(usize, ErrorContext),
+    ResumeFinishedCoroutine(usize, ErrorContext),
+    UnlinkedSymbol(Rc<str>, ErrorContext),
+    GenOpArityMismatch(Rc<str>, usize, usize, ErrorContext),
+    AsyncGenOpInSyncRun(Rc<str>, ErrorContext),
+    HandlesNotEnabled(ErrorContext),
+    LocalIsNotHandle(usize, ErrorContext),
+    AccessMissingHandle(usize, ErrorContext),
+    TaskOpOutsideScheduler(ErrorContext),
+    JoinFailedTask(usize, ErrorContext),
+    SendOnClosedChannel(usize, ErrorContext),
+    RecvOnEmptyChannel(usize, ErrorContext),
+    BranchTargetOutOfRange(usize, ErrorContext),
+    AccessMissingSlot(usize, ErrorContext),
+    AccessEmptySlot(usize, ErrorContext),
+    TopLevelMultiReturn(usize, ErrorContext),
+    ArityMismatch(Rc<str>, usize, usize, ErrorContext),
+    CloneNotEnabled(ErrorContext),
+    GlobalsNotEnabled(ErrorContext),
+    AccessMissingGlobal(usize, ErrorContext),
+    GlobalDoesNotExist(Rc<str>, ErrorContext),
+    SelectNotEnabled(ErrorContext),
+    DuplicateMoveParam(usize, ErrorContext),
+    DuplicateFunName(Rc<str>, ErrorContext),
 }
 
 impl std::fmt::Display for VmError {
     fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
-        fn d(x : &StackTrace) -> String {
-            x.into_iter().map(|(n, i)| format!("    {} at index {}\n", n, i)).collect()
+        fn d(x : &ErrorContext) -> String {
+            let mut s = String::new();
+            if let Some(op) = &x.op {
+                s.push_str(&format!("    op {}\n", op));
+            }
+            if let Some(locals) = &x.locals {
+                s.push_str(&format!("    locals [{}]\n", locals.join(", ")));
+            }
+            s.extend(x.trace.iter().map(|(n, i)| format!("    {} at index {}\n", n, i)));
+            s
         }
 
         match self { 
-            VmError::FunDoesNotExist(fun_index, trace) => 
-                write!(f, "Fun Index {} does not exist: \n{}", fun_index, d(trace)),
-            VmError::DynFunDoesNotExist(trace) => 
-                write!(f, "Dynamic fun does not exist: \n{}", d(trace)),
-            VmError::InstrPointerOutOfRange(instr, trace) => 
-                write!(f, "Instr Index {} does not exist: \n{}", instr, d(trace)),
-            VmError::GenOpDoesNotExist(op_index, trace) => 
-                write!(f, "GenOp {} does not exist: \n{}", op_index, d(trace)),
-            VmError::AccessMissingReturn(trace) => 
-                write!(f, "Attempting to access missing return: \n{}", d(trace)),
-            VmError::AccessMissingLocal(local, trace) => 
-                write!(f, "Attempting to access missing local {}: \n{}", local, d(trace)),
-            VmError::GenOpError(name, error, trace) => 
-                write!(f, "GenOp {} encountered error {}: \n{}", name, error, d(trace)),
-            VmError::TopLevelYield(ip) =>
-                write!(f, "Top Level Yield no supported at instruction: {}", ip),
-            VmError::AccessMissingCoroutine(coroutine, trace) =>
-                write!(f, "Attempting to access missing coroutine {}: \n{}", coroutine, d(trace)),
-            VmError::ResumeFinishedCoroutine(coroutine, trace) =>
-                write!(f, "Attempting to resume finished coroutine {}: \n{}", coroutine, d(trace)),
+            VmError::FunDoesNotExist(fun_index, context) => 
+                write!(f, "Fun Index {} does not exist: \n{}", fun_index, d(context)),
+            VmError::DynFunDoesNotExist(context) => 
+                write!(f, "Dynamic fun does not exist: \n{}", d(context)),
+            VmError::InstrPointerOutOfRange(instr, context) => 
+                write!(f, "Instr Index {} does not exist: \n{}", instr, d(context)),
+            VmError::GenOpDoesNotExist(op_index, context) => 
+                write!(f, "GenOp {} does not exist: \n{}", op_index, d(context)),
+            VmError::AccessMissingReturn(context) => 
+                write!(f, "Attempting to access missing return: \n{}", d(context)),
+            VmError::AccessMissingLocal(local, context) => 
+                write!(f, "Attempting to access missing local {}: \n{}", local, d(context)),
+            VmError::GenOpError(name, error, context) => 
+                write!(f, "GenOp {} encountered error {}: \n{}", name, error, d(context)),
+            VmError::TopLevelYield(ip, context) =>
+                write!(f, "Top Level Yield no supported at instruction {}: \n{}", ip, d(context)),
+            VmError::AccessMissingCoroutine(coroutine, context) =>
+                write!(f, "Attempting to access missing coroutine {}: \n{}", coroutine, d(context)),
+            VmError::ResumeFinishedCoroutine(coroutine, context) =>
+                write!(f, "Attempting to resume finished coroutine {}: \n{}", coroutine, d(context)),
+            VmError::UnlinkedSymbol(name, context) =>
+                write!(f, "Attempting to call unlinked symbol {}: \n{}", name, d(context)),
+            VmError::GenOpArityMismatch(name, expected, actual, context) =>
+                write!(f, "GenOp {} expects {} params but was given {}: \n{}", name, expected, actual, d(context)),
+            VmError::AsyncGenOpInSyncRun(name, context) =>
+                write!(f, "Async GenOp {} requires run_async: \n{}", name, d(context)),
+            VmError::HandlesNotEnabled(context) =>
+                write!(f, "Handles are not enabled for this vm: \n{}", d(context)),
+            VmError::LocalIsNotHandle(local, context) =>
+                write!(f, "Local {} is not a handle of the expected kind: \n{}", local, d(context)),
+            VmError::AccessMissingHandle(handle, context) =>
+                write!(f, "Attempting to access missing handle {}: \n{}", handle, d(context)),
+            VmError::TaskOpOutsideScheduler(context) =>
+                write!(f, "Task op used outside of the scheduler: \n{}", d(context)),
+            VmError::JoinFailedTask(task, context) =>
+                write!(f, "Attempting to join failed task {}: \n{}", task, d(context)),
+            VmError::SendOnClosedChannel(channel, context) =>
+                write!(f, "Attempting to send on closed channel {}: \n{}", channel, d(context)),
+            VmError::RecvOnEmptyChannel(channel, context) =>
+                write!(f, "Attempting to receive from empty channel {} with nothing to suspend: \n{}", channel, d(context)),
+            VmError::BranchTargetOutOfRange(target, context) =>
+                write!(f, "Branch target {} is out of range: \n{}", target, d(context)),
+            VmError::AccessMissingSlot(slot, context) =>
+                write!(f, "Attempting to access missing slot {}: \n{}", slot, d(context)),
+            VmError::AccessEmptySlot(slot, context) =>
+                write!(f, "Attempting to load empty slot {}: \n{}", slot, d(context)),
+            VmError::TopLevelMultiReturn(count, context) =>
+                write!(f, "Top level return of {} values is not supported: \n{}", count, d(context)),
+            VmError::ArityMismatch(name, expected, actual, context) =>
+                write!(f, "Fun {} expects {} params but was given {}: \n{}", name, expected, actual, d(context)),
+            VmError::CloneNotEnabled(context) =>
+                write!(f, "Cloning values is not enabled for this vm: \n{}", d(context)),
+            VmError::GlobalsNotEnabled(context) =>
+                write!(f, "Global ops are not enabled for this vm: \n{}", d(context)),
+            VmError::AccessMissingGlobal(global, context) =>
+                write!(f, "Attempting to access missing global {}: \n{}", global, d(context)),
+            VmError::GlobalDoesNotExist(name, context) =>
+                write!(f, "Global {} does not exist: \n{}", name, d(context)),
+            VmError::SelectNotEnabled(context) =>
+                write!(f, "Selecting from locals is not enabled for this vm: \n{}", d(context)),
+            VmError::DuplicateMoveParam(local, context) =>
+                write!(f, "Local {} is moved into the call more than once: \n{}", local, d(context)),
+            VmError::DuplicateFunName(name, context) =>
+                write!(f, "Fun {} is defined more than once: \n{}", name, d(context)),
+        }
+    }
+}
+
+
+// Note:  Codes are stable across releases.  New variants take the next unused code.
+impl VmError {
+    pub fn code(&self) -> u32 {
+        match self {
+            VmError::FunDoesNotExist(..) => 1,
+            VmError::DynFunDoesNotExist(..) => 2,
+            VmError::InstrPointerOutOfRange(..) => 3,
+            VmError::GenOpDoesNotExist(..) => 4,
+            VmError::AccessMissingReturn(..) => 5,
+            VmError::AccessMissingLocal(..) => 6,
+            VmError::GenOpError(..) => 7,
+            VmError::TopLevelYield(..) => 8,
+            VmError::AccessMissingCoroutine(..) => 9,
+            VmError::ResumeFinishedCoroutine(..) => 10,
+            VmError::UnlinkedSymbol(..) => 11,
+            VmError::GenOpArityMismatch(..) => 12,
+            VmError::AsyncGenOpInSyncRun(..) => 13,
+            VmError::HandlesNotEnabled(..) => 14,
+            VmError::LocalIsNotHandle(..) => 15,
+            VmError::AccessMissingHandle(..) => 16,
+            VmError::TaskOpOutsideScheduler(..) => 17,
+            VmError::JoinFailedTask(..) => 18,
+            VmError::SendOnClosedChannel(..) => 19,
+            VmError::RecvOnEmptyChannel(..) => 20,
+            VmError::BranchTargetOutOfRange(..) => 21,
+            VmError::AccessMissingSlot(..) => 22,
+            VmError::AccessEmptySlot(..) => 23,
+            VmError::TopLevelMultiReturn(..) => 24,
+            VmError::ArityMismatch(..) => 25,
+            VmError::CloneNotEnabled(..) => 26,
+            VmError::GlobalsNotEnabled(..) => 27,
+            VmError::AccessMissingGlobal(..) => 28,
+            VmError::GlobalDoesNotExist(..) => 29,
+            VmError::SelectNotEnabled(..) => 30,
+            VmError::DuplicateMoveParam(..) => 31,
+            VmError::DuplicateFunName(..) => 32,
+        }
+    }
+
+    pub fn summary(&self) -> ErrorSummary {
+        let kind = match self {
+            VmError::FunDoesNotExist(a, _) => ErrorKind::FunDoesNotExist(*a),
+            VmError::DynFunDoesNotExist(_) => ErrorKind::DynFunDoesNotExist,
+            VmError::InstrPointerOutOfRange(a, _) => ErrorKind::InstrPointerOutOfRange(*a),
+            VmError::GenOpDoesNotExist(a, _) => ErrorKind::GenOpDoesNotExist(*a),
+            VmError::AccessMissingReturn(_) => ErrorKind::AccessMissingReturn,
+            VmError::AccessMissingLocal(a, _) => ErrorKind::AccessMissingLocal(*a),
+            VmError::GenOpError(a, b, _) => ErrorKind::GenOpError(Rc::clone(a), b.to_string()),
+            VmError::TopLevelYield(a, _) => ErrorKind::TopLevelYield(*a),
+            VmError::AccessMissingCoroutine(a, _) => ErrorKind::AccessMissingCoroutine(*a),
+            VmError::ResumeFinishedCoroutine(a, _) => ErrorKind::ResumeFinishedCoroutine(*a),
+            VmError::UnlinkedSymbol(a, _) => ErrorKind::UnlinkedSymbol(Rc::clone(a)),
+            VmError::GenOpArityMismatch(a, b, c, _) => ErrorKind::GenOpArityMismatch(Rc::clone(a), *b, *c),
+            VmError::AsyncGenOpInSyncRun(a, _) => ErrorKind::AsyncGenOpInSyncRun(Rc::clone(a)),
+            VmError::HandlesNotEnabled(_) => ErrorKind::HandlesNotEnabled,
+            VmError::LocalIsNotHandle(a, _) => ErrorKind::LocalIsNotHandle(*a),
+            VmError::AccessMissingHandle(a, _) => ErrorKind::AccessMissingHandle(*a),
+            VmError::TaskOpOutsideScheduler(_) => ErrorKind::TaskOpOutsideScheduler,
+            VmError::JoinFailedTask(a, _) => ErrorKind::JoinFailedTask(*a),
+            VmError::SendOnClosedChannel(a, _) => ErrorKind::SendOnClosedChannel(*a),
+            VmError::RecvOnEmptyChannel(a, _) => ErrorKind::RecvOnEmptyChannel(*a),
+            VmError::BranchTargetOutOfRange(a, _) => ErrorKind::BranchTargetOutOfRange(*a),
+            VmError::AccessMissingSlot(a, _) => ErrorKind::AccessMissingSlot(*a),
+            VmError::AccessEmptySlot(a, _) => ErrorKind::AccessEmptySlot(*a),
+            VmError::TopLevelMultiReturn(a, _) => ErrorKind::TopLevelMultiReturn(*a),
+            VmError::ArityMismatch(a, b, c, _) => ErrorKind::ArityMismatch(Rc::clone(a), *b, *c),
+            VmError::CloneNotEnabled(_) => ErrorKind::CloneNotEnabled,
+            VmError::GlobalsNotEnabled(_) => ErrorKind::GlobalsNotEnabled,
+            VmError::AccessMissingGlobal(a, _) => ErrorKind::AccessMissingGlobal(*a),
+            VmError::GlobalDoesNotExist(a, _) => ErrorKind::GlobalDoesNotExist(Rc::clone(a)),
+            VmError::SelectNotEnabled(_) => ErrorKind::SelectNotEnabled,
+            VmError::DuplicateMoveParam(a, _) => ErrorKind::DuplicateMoveParam(*a),
+            VmError::DuplicateFunName(a, _) => ErrorKind::DuplicateFunName(Rc::clone(a)),
+        };
+        ErrorSummary { kind, trace: self.context().trace.clone() }
+    }
+
+    pub fn context(&self) -> &ErrorContext {
+        match self {
+            VmError::FunDoesNotExist(_, context) => context,
+            VmError::DynFunDoesNotExist(context) => context,
+            VmError::InstrPointerOutOfRange(_, context) => context,
+            VmError::GenOpDoesNotExist(_, context) => context,
+            VmError::AccessMissingReturn(context) => context,
+            VmError::AccessMissingLocal(_, context) => context,
+            VmError::GenOpError(_, _, context) => context,
+            VmError::TopLevelYield(_, context) => context,
+            VmError::AccessMissingCoroutine(_, context) => context,
+            VmError::ResumeFinishedCoroutine(_, context) => context,
+            VmError::UnlinkedSymbol(_, context) => context,
+            VmError::GenOpArityMismatch(_, _, _, context) => context,
+            VmError::AsyncGenOpInSyncRun(_, context) => context,
+            VmError::HandlesNotEnabled(context) => context,
+            VmError::LocalIsNotHandle(_, context) => context,
+            VmError::AccessMissingHandle(_, context) => context,
+            VmError::TaskOpOutsideScheduler(context) => context,
+            VmError::JoinFailedTask(_, context) => context,
+            VmError::SendOnClosedChannel(_, context) => context,
+            VmError::RecvOnEmptyChannel(_, context) => context,
+            VmError::BranchTargetOutOfRange(_, context) => context,
+            VmError::AccessMissingSlot(_, context) => context,
+            VmError::AccessEmptySlot(_, context) => context,
+            VmError::TopLevelMultiReturn(_, context) => context,
+            VmError::ArityMismatch(_, _, _, context) => context,
+            VmError::CloneNotEnabled(context) => context,
+            VmError::GlobalsNotEnabled(context) => context,
+            VmError::AccessMissingGlobal(_, context) => context,
+            VmError::GlobalDoesNotExist(_, context) => context,
+            VmError::SelectNotEnabled(context) => context,
+            VmError::DuplicateMoveParam(_, context) => context,
+            VmError::DuplicateFunName(_, context) => context,
         }
     }
 }
 
 impl std::error::Error for VmError { }
+
+#[derive(Debug)]
+pub enum LinkError {
+    DuplicateSymbol(Rc<str>),
+    UnresolvedSymbol(Rc<str>, Rc<str>),
+    UndeclaredGlobal(usize, Rc<str>),
+}
+
+impl std::fmt::Display for LinkError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self { 
+            LinkError::DuplicateSymbol(name) => 
+                write!(f, "Symbol {} is defined more than once", name),
+            LinkError::UnresolvedSymbol(name, fun) => 
+                write!(f, "Symbol {} referenced from {} does not exist", name, fun),
+            LinkError::UndeclaredGlobal(index, fun) => 
+                write!(f, "Global {} referenced from {} is not declared by its module", index, fun),
+        }
+    }
+}
+
+impl std::error::Error for LinkError { }
+
+#[derive(Debug)]
+pub enum BuildError {
+    LocalsDepthMismatch(Rc<str>, usize, usize, usize),
+    ContinueOutsideLoop(Rc<str>, usize),
+    LabelOutOfScope(Rc<str>, usize),
+    LabelNotPlaced(Rc<str>, usize),
+    LabelAlreadyPlaced(Rc<str>, usize),
+    LocalOutOfScope(Rc<str>, usize),
+    UndefinedFun(Rc<str>),
+    Invalid(VmError),
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self { 
+            BuildError::LocalsDepthMismatch(fun, ip, expected, actual) => 
+                write!(f, "Fun {} at index {} has {} locals but the enclosing loop expects {}", fun, ip, actual, expected),
+            BuildError::ContinueOutsideLoop(fun, ip) => 
+                write!(f, "Fun {} at index {} continues a block that is not a loop", fun, ip),
+            BuildError::LabelOutOfScope(fun, ip) => 
+                write!(f, "Fun {} at index {} uses a label that is not in scope", fun, ip),
+            BuildError::LabelNotPlaced(fun, ip) => 
+                write!(f, "Fun {} at index {} uses a label that was never placed", fun, ip),
+            BuildError::LabelAlreadyPlaced(fun, ip) => 
+                write!(f, "Fun {} at index {} places a label that was already placed", fun, ip),
+            BuildError::LocalOutOfScope(fun, ip) => 
+                write!(f, "Fun {} at index {} uses a local that is no longer on the stack", fun, ip),
+            BuildError::UndefinedFun(fun) => 
+                write!(f, "Fun {} was declared but never defined", fun),
+            BuildError::Invalid(error) => 
+                write!(f, "Built fun is invalid: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for BuildError { }
+
+#[derive(Debug)]
+pub enum RegistryError {
+    DuplicateGenOp(Rc<str>),
+}
+
+impl std::fmt::Display for RegistryError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self { 
+            RegistryError::DuplicateGenOp(name) => 
+                write!(f, "GenOp {} is already registered", name),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError { }
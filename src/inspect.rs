@@ -0,0 +1,82 @@
+use std::rc::Rc;
+
+use crate::*;
+use crate::data::*;
+use crate::scheduler::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoroutineState {
+    Active,
+    Running,
+    Finished,
+}
+
+pub struct CoroutineInfo<'a, T> {
+    pub state : CoroutineState,
+    pub fun : Option<Rc<str>>,
+    pub ip : Option<usize>,
+    pub locals : &'a [T],
+    pub children : Vec<CoroutineInfo<'a, T>>,
+}
+
+pub struct FrameCoroutines<'a, T> {
+    // Note:  None for the frames of the vm's own call stack.
+    pub task : Option<Handle>,
+    pub fun : Rc<str>,
+    pub ip : usize,
+    pub coroutines : Vec<CoroutineInfo<'a, T>>,
+}
+
+impl<T, S> Vm<T, S> {
+    // Note:  Reports the coroutines owned by each frame on the call stack starting
+    // from the bottom of the stack and ending with the current frame, followed by the
+    // frames of each task that has not completed.  Running coroutines are on the call
+    // stack themselves, so they have no ip or locals here.
+    pub fn inspect_coroutines(&self) -> Vec<FrameCoroutines<'_, T>> {
+        let mut frames = self.inspect_frames(None, &self.frames, &self.current);
+        for (index, task) in self.tasks.iter().enumerate() {
+            if !matches!(task.state, TaskState::Finished(_) | TaskState::Failed) {
+                frames.extend(self.inspect_frames(Some(Handle::Task(index)), &task.frames, &task.current));
+            }
+        }
+        frames
+    }
+
+    // Note:  Reports the coroutines that were exported with CoExport and are not yet
+    // imported or released.
+    pub fn inspect_exported_coroutines(&self) -> Vec<(Handle, CoroutineInfo<'_, T>)> {
+        self.coroutine_handles.iter().enumerate()
+            .filter_map(|(index, c)| c.as_ref().map(|c| (Handle::Coroutine(index), self.inspect_coroutine(c))))
+            .collect()
+    }
+
+    fn inspect_frames<'a>(&'a self, task : Option<Handle>, frames : &'a [Frame<T>], current : &'a Frame<T>) -> Vec<FrameCoroutines<'a, T>> {
+        frames.iter().chain(std::iter::once(current)).map(|frame| FrameCoroutines {
+            task,
+            fun: self.fun_name(frame),
+            ip: frame.ip,
+            coroutines: frame.coroutines.iter().map(|c| self.inspect_coroutine(c)).collect(),
+        }).collect()
+    }
+
+    fn inspect_coroutine<'a>(&'a self, coroutine : &'a Coroutine<T>) -> CoroutineInfo<'a, T> {
+        match coroutine {
+            Coroutine::Active(frame) => CoroutineInfo {
+                state: CoroutineState::Active,
+                fun: Some(self.fun_name(frame)),
+                ip: Some(frame.ip),
+                locals: &frame.locals,
+                children: frame.coroutines.iter().map(|c| self.inspect_coroutine(c)).collect(),
+            },
+            Coroutine::Running => CoroutineInfo { state: CoroutineState::Running, fun: None, ip: None, locals: &[], children: vec![] },
+            Coroutine::Finished => CoroutineInfo { state: CoroutineState::Finished, fun: None, ip: None, locals: &[], children: vec![] },
+        }
+    }
+
+    fn fun_name(&self, frame : &Frame<T>) -> Rc<str> {
+        match self.funs.get(frame.fun_id).and_then(|versions| versions.get(frame.fun_version)) {
+            Some(def) => Rc::clone(&def.fun.name),
+            None => "<missing>".into(),
+        }
+    }
+}
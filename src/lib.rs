@@ -1,48 +1,277 @@
 
 pub mod error;
 pub mod data;
+pub mod symbol;
+pub mod link;
+pub mod registry;
+pub mod scheduler;
+pub mod channel;
+pub mod inspect;
+pub mod builder;
 
 use crate::error::*;
 use crate::data::*;
+use crate::registry::*;
+use crate::symbol::*;
+use crate::scheduler::*;
+use crate::channel::*;
 
-use std::borrow::Cow;
 use std::rc::Rc;
 
 pub struct Vm<T, S> {
-    funs : Vec<Fun<T>>,
-    ops : Vec<GenOp<T, S>>,
+    // Note:  Each function index holds every version of that function that has
+    // been loaded.  New calls always use the latest version, but frames remember
+    // the version they started on so that replacing a function does not change
+    // the body out from under a running or suspended frame.
+    funs : Vec<Vec<FunDef<T>>>,
+    ops : GenOpRegistry<T, S>,
     globals: Vec<S>,
     frames : Vec<Frame<T>>,
     current : Frame<T>,
+    handle_conv : Option<HandleConv<T>>,
+    select_conv : Option<fn(&T) -> Option<usize>>,
+    // Note:  Slots are never reused so that a stale handle can not alias a newer coroutine.
+    coroutine_handles : Vec<Option<Coroutine<T>>>,
+    // Note:  Task and channel handles are indices as well, so their slots are never
+    // removed.  What a completed task or a closed and drained channel holds is freed, but
+    // each slot stays for the life of the vm and the scheduler scans over all of them.
+    tasks : Vec<Task<T>>,
+    in_scheduler : bool,
+    channels : Vec<Channel<T>>,
+    drop_hook : Option<DropHook<T, S>>,
+    // Note:  Only set when T is Clone.  Ops that duplicate a value fail without it.
+    clone : Option<fn(&T) -> T>,
+    global_conv : Option<GlobalConv<T, S>>,
+    global_names : Vec<Rc<str>>,
+    debug : Option<DebugFmt<T>>,
+}
+
+struct DebugFmt<T> {
+    value : fn(&T) -> String,
+    op : fn(&Op<T>) -> String,
+}
+
+struct GlobalConv<T, S> {
+    load : fn(&S) -> T,
+    store : fn(T) -> S,
+}
+
+struct HandleConv<T> {
+    to_value : fn(Handle) -> T,
+    from_value : fn(&T) -> Option<Handle>,
+}
+
+enum Exit<T> {
+    Return(Option<T>),
+    Await(Rc<str>, GenFuture<T>),
+    Yield,
+    Wait(usize),
+    Join(Handle),
+    Recv(Handle),
 }
 
 impl<T : Clone, S> Vm<T, S> {
-    pub fn new(funs : Vec<Fun<T>>, ops : Vec<GenOp<T, S>>) -> Self {
-        let current = Frame { fun_id: 0, ip: 0, ret: None, branch: false, dyn_call: None, locals: vec![], coroutines: vec![] };
-        Vm { funs, ops, globals: vec![], frames: vec![], current }
+    pub fn new(funs : Vec<impl Into<FunDef<T>>>, ops : impl Into<GenOpRegistry<T, S>>) -> Self {
+        let mut vm = Self::new_without_clone(funs, ops);
+        vm.clone = Some(T::clone);
+        vm
+    }
+
+    pub fn try_new(funs : Vec<impl Into<FunDef<T>>>, ops : impl Into<GenOpRegistry<T, S>>) -> Result<Self, VmError> {
+        let mut vm = Self::try_new_without_clone(funs, ops)?;
+        vm.clone = Some(T::clone);
+        Ok(vm)
+    }
+}
+
+impl<T, S> Vm<T, S> {
+    // Note:  For value types that are not Clone.  Dup, PushLocal, Load, CoDup, ChanSend,
+    // CoYield, CoResumeWith, ReturnLocals with a repeated slot and the cloning call ops
+    // report CloneNotEnabled.
+    pub fn new_without_clone(funs : Vec<impl Into<FunDef<T>>>, ops : impl Into<GenOpRegistry<T, S>>) -> Self {
+        let current = empty_frame();
+        let funs = funs.into_iter().map(|fun| vec![fun.into()]).collect();
+        Vm { funs, ops: ops.into(), globals: vec![], frames: vec![], current, handle_conv: None, select_conv: None, coroutine_handles: vec![], tasks: vec![], in_scheduler: false, channels: vec![], drop_hook: None, clone: None, global_conv: None, global_names: vec![], debug: None }
+    }
+
+    // Note:  Checks every Gen instruction against the registry and that every fun has
+    // its own name before anything runs.
+    pub fn try_new_without_clone(funs : Vec<impl Into<FunDef<T>>>, ops : impl Into<GenOpRegistry<T, S>>) -> Result<Self, VmError> {
+        let ops = ops.into();
+        let funs = funs.into_iter().map(Into::into).collect::<Vec<FunDef<T>>>();
+        if let Err(LinkError::DuplicateSymbol(name)) = SymbolTable::new(&funs) {
+            return Err(VmError::DuplicateFunName(name, vec![].into()));
+        }
+        for FunDef { fun, .. } in &funs {
+            check_fun(fun, &ops)?;
+        }
+        Ok(Self::new_without_clone(funs, ops))
     }
 
     pub fn with_globals(&mut self, globals: Vec<S>) -> Vec<S> { 
         std::mem::replace(&mut self.globals, globals)
     }
 
+    pub fn globals(&self) -> &[S] {
+        &self.globals
+    }
+
+    pub fn globals_mut(&mut self) -> &mut Vec<S> {
+        &mut self.globals
+    }
+
+    // Note:  Counts the current frame along with every caller below it.  After a run or
+    // error the frames are left as they were when execution stopped, until the next run.
+    pub fn call_depth(&self) -> usize {
+        self.frames.len() + 1
+    }
+
+    pub fn current_frame(&self) -> &Frame<T> {
+        &self.current
+    }
+
+    // Note:  Iterates from the outermost caller to the current frame.
+    pub fn frames(&self) -> impl Iterator<Item = &Frame<T>> {
+        self.frames.iter().chain(std::iter::once(&self.current))
+    }
+
+    // Note:  Vm::new does not check for duplicate names, in which case this finds the
+    // first fun with the name.  Use try_new to rule that out.
+    pub fn fun_index(&self, name : &str) -> Option<usize> {
+        self.funs.iter().position(|versions| &*latest(versions).fun.name == name)
+    }
+
+    pub fn enable_handles(&mut self) where T : HandleValue {
+        self.handle_conv = Some(HandleConv { to_value: T::from_handle, from_value: T::to_handle });
+    }
+
+    pub fn enable_select(&mut self) where T : SelectValue {
+        self.select_conv = Some(T::to_select);
+    }
+
+    // Note:  Errors raised after this include the full op and the locals of the top frame.
+    pub fn enable_debug(&mut self) where T : std::fmt::Debug {
+        self.debug = Some(DebugFmt { value: |v| format!("{:?}", v), op: |op| format!("{:?}", op) });
+    }
+
+    pub fn enable_globals(&mut self) where S : Clone + Into<T>, T : Into<S> {
+        self.global_conv = Some(GlobalConv { load: |s| s.clone().into(), store: T::into });
+    }
+
+    // Note:  Names the globals by position for LoadGlobalSym and StoreGlobalSym.  The
+    // values themselves still come from with_globals.
+    pub fn declare_globals(&mut self, names : Vec<Rc<str>>) {
+        self.global_names = names;
+    }
+
+    pub fn global_index(&self, name : &str) -> Option<usize> {
+        self.global_names.iter().position(|global| &**global == name)
+    }
+
+    pub fn global(&self, index : usize) -> Option<&S> {
+        self.globals.get(index)
+    }
+
+    pub fn global_mut(&mut self, index : usize) -> Option<&mut S> {
+        self.globals.get_mut(index)
+    }
+
+    pub fn on_coroutine_drop(&mut self, hook : DropHook<T, S>) {
+        self.drop_hook = Some(hook);
+    }
+
+    pub fn verify(&self) -> Result<(), VmError> {
+        for versions in &self.funs {
+            latest(versions).fun.verify()?;
+        }
+        Ok(())
+    }
+
+    pub fn gen_op_index(&self, name : &str) -> Option<usize> {
+        self.ops.index_of(name)
+    }
+
+    pub fn replace_fun(&mut self, index : usize, fun : impl Into<FunDef<T>>) -> Result<usize, VmError> {
+        if index >= self.funs.len() {
+            return Err(VmError::FunDoesNotExist(index, vec![].into()));
+        }
+
+        let fun = fun.into();
+        check_fun(&fun.fun, &self.ops)?;
+        self.funs[index].push(fun);
+
+        // Note:  The frames left behind by a finished or failed run are never resumed, so
+        // only the suspended coroutines they hold are counted.  Likewise only tasks that
+        // have not completed are counted.
+        let latest = self.funs[index].len() - 1;
+        let mut stale = 0;
+        for frame in self.frames.iter().chain(std::iter::once(&self.current)) {
+            stale += count_stale_coroutines(&frame.coroutines, index, latest);
+        }
+        stale += count_stale_coroutines(self.coroutine_handles.iter().flatten(), index, latest);
+        for task in self.tasks.iter().filter(|task| !matches!(task.state, TaskState::Finished(_) | TaskState::Failed)) {
+            for frame in task.frames.iter().chain(std::iter::once(&task.current)) {
+                stale += count_stale_frames(frame, index, latest);
+            }
+        }
+        Ok(stale)
+    }
+
     pub fn run(&mut self, entry : usize) -> Result<Option<T>, VmError> {
-        self.current.fun_id = entry;
+        self.enter_entry(entry)?;
+
+        match self.execute()? {
+            Exit::Return(v) => Ok(v),
+            Exit::Await(name, _) => Err(VmError::AsyncGenOpInSyncRun(name, self.error_context())),
+            Exit::Yield | Exit::Wait(_) | Exit::Join(_) | Exit::Recv(_) => Err(VmError::TaskOpOutsideScheduler(self.error_context())),
+        }
+    }
+
+    // Note:  The vm parks on the async GenOp instruction until its future completes,
+    // so any executor can drive this future.
+    pub async fn run_async(&mut self, entry : usize) -> Result<Option<T>, VmError> {
+        self.enter_entry(entry)?;
 
+        loop {
+            match self.execute()? {
+                Exit::Return(v) => { return Ok(v); },
+                Exit::Await(name, future) => {
+                    match future.await {
+                        Ok(v) => { 
+                            self.current.ret = v;
+                            self.current.ip += 1;
+                        },
+                        Err(e) => {
+                            return Err(VmError::GenOpError(name, e, self.error_context()));
+                        },
+                    }
+                },
+                Exit::Yield | Exit::Wait(_) | Exit::Join(_) | Exit::Recv(_) => {
+                    return Err(VmError::TaskOpOutsideScheduler(self.error_context()));
+                },
+            }
+        }
+    }
+
+    fn execute(&mut self) -> Result<Exit<T>, VmError> {
         loop {
             if self.current.fun_id >= self.funs.len() {
-                return Err(VmError::FunDoesNotExist(self.current.fun_id, self.stack_trace()));
+                return Err(VmError::FunDoesNotExist(self.current.fun_id, self.error_context()));
             }
 
-            if self.current.ip >= self.funs[self.current.fun_id].instrs.len() {
+            if self.current.ip >= self.funs[self.current.fun_id][self.current.fun_version].fun.instrs.len() {
                 // Note:  if the current function isn't pushed onto the return stack, then the
                 // stack trace will leave out the current function where the problem is occurring.
-                return Err(VmError::InstrPointerOutOfRange(self.current.ip, self.stack_trace()));
+                return Err(VmError::InstrPointerOutOfRange(self.current.ip, self.error_context()));
             }
 
-            match self.funs[self.current.fun_id].instrs[self.current.ip] {
+            match self.funs[self.current.fun_id][self.current.fun_version].fun.instrs[self.current.ip] {
                 Op::Gen(op_index, ref params) if op_index < self.ops.len() => {
-                    match &self.ops[op_index] {
+                    let entry = self.ops.get(op_index).unwrap();
+                    if let Some(arity) = entry.arity && arity != params.len() {
+                        return Err(VmError::GenOpArityMismatch(Rc::clone(entry.op.name()), arity, params.len(), self.error_context()));
+                    }
+                    match &entry.op {
                         GenOp::Vm { name, op } => {
                             let env = VmEnv { 
                                 globals: &mut self.globals,
@@ -55,7 +284,7 @@ impl<T : Clone, S> Vm<T, S> {
                                     self.current.ret = v;
                                 },
                                 Err(e) => {
-                                    return Err(VmError::GenOpError(Rc::clone(name), e, self.stack_trace()));
+                                    return Err(VmError::GenOpError(Rc::clone(name), e, self.error_context()));
                                 },
                             }
                         },
@@ -65,7 +294,7 @@ impl<T : Clone, S> Vm<T, S> {
                                     self.current.ret = v;
                                 },
                                 Err(e) => {
-                                    return Err(VmError::GenOpError(Rc::clone(name), e, self.stack_trace()));
+                                    return Err(VmError::GenOpError(Rc::clone(name), e, self.error_context()));
                                 },
                             }
                         },
@@ -75,7 +304,7 @@ impl<T : Clone, S> Vm<T, S> {
                                     self.current.ret = v;
                                 },
                                 Err(e) => {
-                                    return Err(VmError::GenOpError(Rc::clone(name), e, self.stack_trace()));
+                                    return Err(VmError::GenOpError(Rc::clone(name), e, self.error_context()));
                                 },
                             }
                         },
@@ -85,16 +314,20 @@ impl<T : Clone, S> Vm<T, S> {
                                     self.current.ret = v;
                                 },
                                 Err(e) => {
-                                    return Err(VmError::GenOpError(Rc::clone(name), e, self.stack_trace()));
+                                    return Err(VmError::GenOpError(Rc::clone(name), e, self.error_context()));
                                 },
                             }
                         },
+                        GenOp::Async { name, op } => {
+                            let future = op(&mut self.current.locals, params);
+                            return Ok(Exit::Await(Rc::clone(name), future));
+                        },
                     }
                     self.current.ip += 1;
                 },
                 Op::Gen(op_index, _) => {
                     // Note:  Indicate current function for stack trace.
-                    return Err(VmError::GenOpDoesNotExist(op_index, self.stack_trace()));
+                    return Err(VmError::GenOpDoesNotExist(op_index, self.error_context()));
                 },
                 Op::Branch(target) if self.current.branch => {
                     self.current.ip = target;
@@ -102,90 +335,219 @@ impl<T : Clone, S> Vm<T, S> {
                 Op::Branch(_) => { 
                     self.current.ip += 1;
                 },
+                Op::BranchFalse(target) if !self.current.branch => {
+                    self.current.ip = target;
+                },
+                Op::BranchFalse(_) => { 
+                    self.current.ip += 1;
+                },
+                Op::Jump(target) => {
+                    self.current.ip = target;
+                },
+                Op::SetBranch => {
+                    self.current.branch = true;
+                    self.current.ip += 1;
+                },
+                Op::ClearBranch => {
+                    self.current.branch = false;
+                    self.current.ip += 1;
+                },
+                Op::NotBranch => {
+                    self.current.branch = !self.current.branch;
+                    self.current.ip += 1;
+                },
+                // Note:  A missing or out of range selection goes to the default target.  The
+                // selection is consumed so a later Switch does not reuse a stale one.
+                Op::Switch(ref targets, default) => {
+                    self.current.ip = match self.current.select.take() {
+                        Some(index) if index < targets.len() => targets[index],
+                        _ => default,
+                    };
+                },
+                Op::SwitchLocal(_, _, _) if self.select_conv.is_none() => {
+                    return Err(VmError::SelectNotEnabled(self.error_context()));
+                },
+                Op::SwitchLocal(local, ref targets, default) => {
+                    let to_select = self.select_conv.unwrap();
+                    let select = match self.current.locals.get(local) {
+                        Some(value) => to_select(value),
+                        None => {
+                            return Err(VmError::AccessMissingLocal(local, self.error_context()));
+                        },
+                    };
+                    self.current.ip = match select {
+                        Some(index) if index < targets.len() => targets[index],
+                        _ => default,
+                    };
+                },
+                Op::Call(fun_index, _) | Op::CallMove(fun_index, _) if fun_index >= self.funs.len() => {
+                    return Err(VmError::FunDoesNotExist(fun_index, self.error_context()));
+                },
+                Op::DynCall(_) | Op::DynCallMove(_) if self.current.dyn_call.is_some_and(|fun_index| fun_index >= self.funs.len()) => {
+                    return Err(VmError::FunDoesNotExist(self.current.dyn_call.unwrap(), self.error_context()));
+                },
                 Op::Call(fun_index, ref params) => {
                     let mut new_locals = vec![];
                     for param in params {
-                        match get_local(*param, Cow::Borrowed(&self.current.locals)) {
+                        match get_local(*param, &self.current.locals, self.clone) {
                             Ok(v) => { new_locals.push(v); },
                             Err(f) => { 
-                                return Err(f(self.stack_trace()));
+                                return Err(f(self.error_context()));
                             },
                         }
                     }
+                    let frame = self.enter_frame(fun_index, new_locals)?;
+                    self.current.ip += 1;
+                    let current = std::mem::replace(&mut self.current, frame);
+                    self.frames.push(current);
+                },
+                // Note:  The params are moved out of the caller instead of cloned.  The
+                // remaining locals keep their order and shift down to fill the gaps.
+                Op::CallMove(fun_index, ref params) => {
+                    self.check_params(fun_index, params.len())?;
+                    let new_locals = match move_locals(&mut self.current.locals, params) {
+                        Ok(v) => v,
+                        Err(f) => { 
+                            return Err(f(self.error_context()));
+                        },
+                    };
+                    let frame = self.enter_frame(fun_index, new_locals)?;
                     self.current.ip += 1;
-                    let current = std::mem::replace(&mut self.current, Frame { fun_id: fun_index, ip: 0, ret: None, branch: false, dyn_call: None, locals: new_locals, coroutines: vec![] });
+                    let current = std::mem::replace(&mut self.current, frame);
                     self.frames.push(current);
                 },
+                Op::CallSym(ref name, _) => {
+                    return Err(VmError::UnlinkedSymbol(Rc::clone(name), self.error_context()));
+                },
                 Op::DynCall(ref params) if self.current.dyn_call.is_some() => {
                     let mut new_locals = vec![];
                     for param in params {
-                        match get_local(*param, Cow::Borrowed(&self.current.locals)) {
+                        match get_local(*param, &self.current.locals, self.clone) {
                             Ok(v) => { new_locals.push(v); },
                             Err(f) => { 
-                                return Err(f(self.stack_trace()));
+                                return Err(f(self.error_context()));
                             },
                         }
                     }
                     let target_fun_id = self.current.dyn_call.unwrap();
+                    let frame = self.enter_frame(target_fun_id, new_locals)?;
                     self.current.ip += 1;
-                    let current = std::mem::replace(&mut self.current, Frame { fun_id: target_fun_id, ip: 0, ret: None, branch: false, dyn_call: None, locals: new_locals, coroutines: vec![] });
+                    let current = std::mem::replace(&mut self.current, frame);
                     self.frames.push(current);
                 },
-                Op::DynCall(_) => {
-                    return Err(VmError::DynFunDoesNotExist(self.stack_trace()));
+                Op::DynCallMove(ref params) if self.current.dyn_call.is_some() => {
+                    let target_fun_id = self.current.dyn_call.unwrap();
+                    self.check_params(target_fun_id, params.len())?;
+                    let new_locals = match move_locals(&mut self.current.locals, params) {
+                        Ok(v) => v,
+                        Err(f) => { 
+                            return Err(f(self.error_context()));
+                        },
+                    };
+                    let frame = self.enter_frame(target_fun_id, new_locals)?;
+                    self.current.ip += 1;
+                    let current = std::mem::replace(&mut self.current, frame);
+                    self.frames.push(current);
+                },
+                Op::DynCall(_) | Op::DynCallMove(_) => {
+                    return Err(VmError::DynFunDoesNotExist(self.error_context()));
                 },
                 Op::ReturnLocal(slot) => {
-                    let current_locals = std::mem::replace(&mut self.current.locals, vec![]);
-
-                    let ret_target = match get_local(slot, Cow::Owned(current_locals)) {
+                    let ret_target = match take_local(slot, &mut self.current.locals) {
                         Ok(v) => v,
                         Err(f) => { 
-                            return Err(f(self.stack_trace()));
+                            return Err(f(self.error_context()));
                         },
                     };
 
                     match self.frames.pop() {
                         // Note:  if the stack is empty then all execution is finished
                         None => {
-                            return Ok(Some(ret_target));
+                            return Ok(Exit::Return(Some(ret_target)));
                         },
                         Some(frame) => {
-                            self.current = frame;
+                            let returned = std::mem::replace(&mut self.current, frame);
+                            self.drop_coroutines(returned.coroutines);
                             self.current.ret = Some(ret_target);
                         },
                     }
                 },
+                // Note:  The values are appended to the caller's locals in order and the
+                // caller's ret is cleared.  A top level return of a single value behaves
+                // like ReturnLocal.
+                Op::ReturnLocals(ref slots) if self.frames.is_empty() && slots.len() > 1 => {
+                    return Err(VmError::TopLevelMultiReturn(slots.len(), self.error_context()));
+                },
+                Op::ReturnLocals(ref slots) => {
+                    if let Some(slot) = slots.iter().find(|slot| **slot >= self.current.locals.len()) {
+                        return Err(VmError::AccessMissingLocal(*slot, self.error_context()));
+                    }
+                    // Note:  Each value is moved out of its slot.  A slot that is returned more
+                    // than once is cloned from its first use.
+                    let firsts = slots.iter().map(|slot| slots.iter().position(|s| s == slot).unwrap()).collect::<Vec<_>>();
+                    let clone = match firsts.iter().enumerate().any(|(i, first)| i != *first) {
+                        true => Some(self.clone_fn()?),
+                        false => None,
+                    };
+                    let mut locals = std::mem::take(&mut self.current.locals).into_iter().map(Some).collect::<Vec<_>>();
+                    let mut rets = Vec::with_capacity(slots.len());
+                    for (i, first) in firsts.into_iter().enumerate() {
+                        let value = match locals[slots[i]].take() {
+                            Some(value) => value,
+                            None => clone.unwrap()(&rets[first]),
+                        };
+                        rets.push(value);
+                    }
+
+                    match self.frames.pop() {
+                        None => {
+                            return Ok(Exit::Return(rets.pop()));
+                        },
+                        Some(frame) => {
+                            let returned = std::mem::replace(&mut self.current, frame);
+                            self.drop_coroutines(returned.coroutines);
+                            self.current.ret = None;
+                            self.current.locals.append(&mut rets);
+                        },
+                    }
+                },
                 Op::Return => {
                     match self.frames.pop() {
                         // Note:  if the stack is empty then all execution is finished
                         None => {
-                            return Ok(None);
+                            return Ok(Exit::Return(None));
                         },
                         Some(frame) => {
-                            self.current = frame;
+                            let returned = std::mem::replace(&mut self.current, frame);
+                            self.drop_coroutines(returned.coroutines);
                             self.current.ret = None;
                         },
                     }
                 },
                 Op::CoYield(slot) => {
 
-                    let ret_target = match get_local(slot, Cow::Borrowed(&self.current.locals)) {
+                    let ret_target = match get_local(slot, &self.current.locals, self.clone) {
                         Ok(v) => v,
                         Err(f) => { 
-                            return Err(f(self.stack_trace()));
+                            return Err(f(self.error_context()));
                         },
                     };
 
                     match self.frames.pop() {
                         None => {
                             // Note: Top level yields are not supported.
-                            return Err(VmError::TopLevelYield(self.current.ip)); 
+                            return Err(VmError::TopLevelYield(self.current.ip, self.error_context())); 
                         },
                         Some(frame) => {
                             self.current.ip += 1;
                             let coroutine = std::mem::replace(&mut self.current, frame);
                             self.current.ret = Some(ret_target);
                             match self.current.coroutines.iter().position(co_is_running) {
+                                // Note:  Yielding out of a cleanup section ends the cancellation.
+                                Some(index) if coroutine.cancelling => {
+                                    self.current.coroutines.remove(index);
+                                    self.drop_coroutine(Coroutine::Active(coroutine));
+                                },
                                 Some(index) => {
                                     let _ = std::mem::replace(&mut self.current.coroutines[index], Coroutine::Active(coroutine));
                                 },
@@ -200,23 +562,55 @@ impl<T : Clone, S> Vm<T, S> {
                     match self.frames.pop() {
                         None => {
                             // Note: Top level yields are not supported.
-                            return Err(VmError::TopLevelYield(self.current.ip)); 
+                            return Err(VmError::TopLevelYield(self.current.ip, self.error_context())); 
                         },
                         Some(frame) => {
-                            self.current = frame;
+                            let coroutine = std::mem::replace(&mut self.current, frame);
                             self.current.ret = None;
 
                             match self.current.coroutines.iter().position(co_is_running) {
+                                Some(index) if coroutine.cancelling => {
+                                    self.current.coroutines.remove(index);
+                                    self.drop_coroutine(Coroutine::Active(coroutine));
+                                },
                                 Some(index) => {
                                     let _ = std::mem::replace(&mut self.current.coroutines[index], Coroutine::Finished);
+                                    self.drop_coroutines(coroutine.coroutines);
                                 },
                                 None => { 
                                     self.current.coroutines.push(Coroutine::Finished);
+                                    self.drop_coroutines(coroutine.coroutines);
                                 },
                             }
                         },
                     }
                 },
+                Op::CoSpawn(fun_index, _) if fun_index >= self.funs.len() => {
+                    return Err(VmError::FunDoesNotExist(fun_index, self.error_context()));
+                },
+                Op::CoSpawn(fun_index, ref params) => {
+                    let mut new_locals = vec![];
+                    for param in params {
+                        match get_local(*param, &self.current.locals, self.clone) {
+                            Ok(v) => { new_locals.push(v); },
+                            Err(f) => { 
+                                return Err(f(self.error_context()));
+                            },
+                        }
+                    }
+                    // Note:  The spawned coroutine always lands at the end of the coroutine list
+                    // and does not run until it is resumed.
+                    let frame = self.enter_frame(fun_index, new_locals)?;
+                    self.current.coroutines.push(Coroutine::Active(frame));
+                    self.current.ip += 1;
+                },
+                Op::CoIsFinished(coroutine) if coroutine < self.current.coroutines.len() => {
+                    self.current.branch = !self.current.coroutines[coroutine].is_alive();
+                    self.current.ip += 1;
+                },
+                Op::CoIsFinished(coroutine) => {
+                    return Err(VmError::AccessMissingCoroutine(coroutine, self.error_context()));
+                },
                 Op::CoResume(coroutine) if coroutine < self.current.coroutines.len() => {
                     match std::mem::replace(&mut self.current.coroutines[coroutine], Coroutine::Running) { 
                         Coroutine::Active(frame) => {
@@ -225,114 +619,610 @@ impl<T : Clone, S> Vm<T, S> {
                             self.frames.push(old_current);
                         },
                         Coroutine::Finished => {
-                            return Err(VmError::ResumeFinishedCoroutine(coroutine, self.stack_trace()))
+                            return Err(VmError::ResumeFinishedCoroutine(coroutine, self.error_context()))
                         },
                         Coroutine::Running => { unreachable!(); },
                     }
                 },
                 Op::CoResume(coroutine) => {
-                    return Err(VmError::AccessMissingCoroutine(coroutine, self.stack_trace()));
+                    return Err(VmError::AccessMissingCoroutine(coroutine, self.error_context()));
+                },
+                Op::CoResumeWith(coroutine, slot) if coroutine < self.current.coroutines.len() => {
+                    let value = match get_local(slot, &self.current.locals, self.clone) {
+                        Ok(v) => v,
+                        Err(f) => { 
+                            return Err(f(self.error_context()));
+                        },
+                    };
+
+                    match std::mem::replace(&mut self.current.coroutines[coroutine], Coroutine::Running) { 
+                        Coroutine::Active(frame) => {
+                            self.current.ip += 1;
+                            let old_current = std::mem::replace(&mut self.current, frame);
+                            self.frames.push(old_current);
+                            // Note:  The resumed coroutine sees the sent value as the result of its CoYield.
+                            self.current.ret = Some(value);
+                        },
+                        Coroutine::Finished => {
+                            return Err(VmError::ResumeFinishedCoroutine(coroutine, self.error_context()))
+                        },
+                        Coroutine::Running => { unreachable!(); },
+                    }
+                },
+                Op::CoResumeWith(coroutine, _) => {
+                    return Err(VmError::AccessMissingCoroutine(coroutine, self.error_context()));
                 },
                 Op::CoDrop(coroutine) if coroutine < self.current.coroutines.len() => {
-                    self.current.coroutines.remove(coroutine);
+                    let target = self.current.coroutines.remove(coroutine);
+                    self.drop_coroutine(target);
                     self.current.ip += 1;
                 },
+                Op::CoOnCancel(target) => {
+                    self.current.cleanup = Some(target);
+                    self.current.ip += 1;
+                },
+                // Note:  A coroutine that registered a cleanup section with CoOnCancel is resumed
+                // at that section and is only discarded once it finishes or yields.  Otherwise it
+                // is discarded immediately.
+                Op::CoCancel(coroutine) if coroutine < self.current.coroutines.len() => {
+                    match std::mem::replace(&mut self.current.coroutines[coroutine], Coroutine::Running) {
+                        Coroutine::Active(mut frame) if frame.cleanup.is_some() => {
+                            frame.ip = frame.cleanup.take().unwrap();
+                            frame.cancelling = true;
+                            self.current.ip += 1;
+                            let old_current = std::mem::replace(&mut self.current, frame);
+                            self.frames.push(old_current);
+                        },
+                        target => {
+                            self.current.coroutines.remove(coroutine);
+                            self.drop_coroutine(target);
+                            self.current.ip += 1;
+                        },
+                    }
+                },
+                Op::CoCancel(coroutine) => {
+                    return Err(VmError::AccessMissingCoroutine(coroutine, self.error_context()));
+                },
                 Op::CoDrop(coroutine) => {
-                    return Err(VmError::AccessMissingCoroutine(coroutine, self.stack_trace()));
+                    return Err(VmError::AccessMissingCoroutine(coroutine, self.error_context()));
+                },
+                Op::TaskSpawn(_, _) | Op::TaskJoin(_) if self.handle_conv.is_none() => {
+                    return Err(VmError::HandlesNotEnabled(self.error_context()));
+                },
+                Op::TaskSpawn(fun_index, _) if fun_index >= self.funs.len() => {
+                    return Err(VmError::FunDoesNotExist(fun_index, self.error_context()));
+                },
+                Op::TaskSpawn(fun_index, ref params) => {
+                    let mut new_locals = vec![];
+                    for param in params {
+                        match get_local(*param, &self.current.locals, self.clone) {
+                            Ok(v) => { new_locals.push(v); },
+                            Err(f) => { 
+                                return Err(f(self.error_context()));
+                            },
+                        }
+                    }
+                    let current = self.enter_frame(fun_index, new_locals)?;
+                    self.tasks.push(Task { frames: vec![], current, state: TaskState::Ready });
+                    let to_value = self.handle_conv.as_ref().unwrap().to_value;
+                    self.current.locals.push(to_value(Handle::Task(self.tasks.len() - 1)));
+                    self.current.ip += 1;
+                },
+                // Note:  The scheduler moves past TaskYield and TaskWait when it parks the task,
+                // but TaskJoin is executed again once the joined task completes.
+                Op::TaskYield => {
+                    return Ok(Exit::Yield);
+                },
+                Op::TaskWait(event) => {
+                    return Ok(Exit::Wait(event));
+                },
+                Op::TaskJoin(local) => {
+                    let task = match self.handle_at(local)? {
+                        Handle::Task(task) => task,
+                        _ => {
+                            return Err(VmError::LocalIsNotHandle(local, self.error_context()));
+                        },
+                    };
+                    match self.join_task(task) {
+                        Join::Ready(v) => {
+                            self.current.ret = v;
+                            self.current.ip += 1;
+                        },
+                        Join::Pending => {
+                            return Ok(Exit::Join(Handle::Task(task)));
+                        },
+                        Join::Failed => {
+                            return Err(VmError::JoinFailedTask(task, self.error_context()));
+                        },
+                        Join::Missing => {
+                            return Err(VmError::AccessMissingHandle(task, self.error_context()));
+                        },
+                    }
+                },
+                Op::ChanNew if self.handle_conv.is_none() => {
+                    return Err(VmError::HandlesNotEnabled(self.error_context()));
+                },
+                Op::ChanNew => {
+                    self.channels.push(Channel::new());
+                    let to_value = self.handle_conv.as_ref().unwrap().to_value;
+                    self.current.locals.push(to_value(Handle::Channel(self.channels.len() - 1)));
+                    self.current.ip += 1;
+                },
+                Op::ChanSend(channel, value) => {
+                    let channel = self.channel_at(channel)?;
+                    let value = match get_local(value, &self.current.locals, self.clone) {
+                        Ok(v) => v,
+                        Err(f) => { 
+                            return Err(f(self.error_context()));
+                        },
+                    };
+                    if self.channels[channel].closed {
+                        return Err(VmError::SendOnClosedChannel(channel, self.error_context()));
+                    }
+                    self.channels[channel].queue.push_back(value);
+                    self.wake_receivers(channel);
+                    self.current.ip += 1;
+                },
+                Op::ChanClose(channel) => {
+                    let channel = self.channel_at(channel)?;
+                    self.channels[channel].closed = true;
+                    self.wake_receivers(channel);
+                    self.current.ip += 1;
+                },
+                // Note:  A receive sets branch when the channel is closed and drained.  On an
+                // empty channel the scheduler blocks the whole task, while outside of the scheduler
+                // the current frame is suspended like a CoYield with no value.  In both cases the
+                // instruction pointer stays on the receive so that it is tried again on resume.
+                Op::ChanRecv(channel) => {
+                    let channel = self.channel_at(channel)?;
+                    match self.channels[channel].queue.pop_front() {
+                        Some(v) => {
+                            self.current.ret = Some(v);
+                            self.current.branch = false;
+                            self.current.ip += 1;
+                        },
+                        None if self.channels[channel].closed => {
+                            std::mem::take(&mut self.channels[channel].queue);
+                            self.current.ret = None;
+                            self.current.branch = true;
+                            self.current.ip += 1;
+                        },
+                        None if self.in_scheduler => {
+                            return Ok(Exit::Recv(Handle::Channel(channel)));
+                        },
+                        None => {
+                            match self.frames.pop() {
+                                None => {
+                                    return Err(VmError::RecvOnEmptyChannel(channel, self.error_context()));
+                                },
+                                Some(frame) => {
+                                    let coroutine = std::mem::replace(&mut self.current, frame);
+                                    self.current.ret = None;
+                                    match self.current.coroutines.iter().position(co_is_running) {
+                                        Some(index) => {
+                                            let _ = std::mem::replace(&mut self.current.coroutines[index], Coroutine::Active(coroutine));
+                                        },
+                                        None => { 
+                                            self.current.coroutines.push(Coroutine::Active(coroutine));
+                                        },
+                                    }
+                                },
+                            }
+                        },
+                    }
+                },
+                Op::CoExport(_) if self.handle_conv.is_none() => {
+                    return Err(VmError::HandlesNotEnabled(self.error_context()));
+                },
+                Op::CoExport(coroutine) if coroutine < self.current.coroutines.len() => {
+                    let target = self.current.coroutines.remove(coroutine);
+                    let handle = Handle::Coroutine(self.coroutine_handles.len());
+                    self.coroutine_handles.push(Some(target));
+                    let to_value = self.handle_conv.as_ref().unwrap().to_value;
+                    self.current.locals.push(to_value(handle));
+                    self.current.ip += 1;
+                },
+                Op::CoExport(coroutine) => {
+                    return Err(VmError::AccessMissingCoroutine(coroutine, self.error_context()));
+                },
+                Op::CoImport(_) if self.handle_conv.is_none() => {
+                    return Err(VmError::HandlesNotEnabled(self.error_context()));
+                },
+                Op::CoImport(local) => {
+                    let handle = match self.handle_at(local)? {
+                        Handle::Coroutine(handle) => handle,
+                        _ => {
+                            return Err(VmError::LocalIsNotHandle(local, self.error_context()));
+                        },
+                    };
+                    match self.coroutine_handles.get_mut(handle).and_then(Option::take) {
+                        Some(target) => {
+                            self.current.coroutines.push(target);
+                            self.current.ip += 1;
+                        },
+                        None => {
+                            return Err(VmError::AccessMissingHandle(handle, self.error_context()));
+                        },
+                    }
+                },
+                Op::CoRelease(_) if self.handle_conv.is_none() => {
+                    return Err(VmError::HandlesNotEnabled(self.error_context()));
+                },
+                // Note:  Drops an exported coroutine without importing it.  The slot stays
+                // empty so the handle can not alias a later coroutine.
+                Op::CoRelease(local) => {
+                    let handle = match self.handle_at(local)? {
+                        Handle::Coroutine(handle) => handle,
+                        _ => {
+                            return Err(VmError::LocalIsNotHandle(local, self.error_context()));
+                        },
+                    };
+                    match self.coroutine_handles.get_mut(handle).and_then(Option::take) {
+                        Some(target) => {
+                            self.drop_coroutine(target);
+                            self.current.ip += 1;
+                        },
+                        None => {
+                            return Err(VmError::AccessMissingHandle(handle, self.error_context()));
+                        },
+                    }
                 },
                 Op::CoDup(coroutine) if coroutine < self.current.coroutines.len() => {
-                    let target = self.current.coroutines[coroutine].clone();
+                    let clone = self.clone_fn()?;
+                    let target = self.current.coroutines[coroutine].clone_with(clone);
                     self.current.coroutines.push(target);
                     self.current.ip += 1;
                 },
                 Op::CoDup(coroutine) => {
-                    return Err(VmError::AccessMissingCoroutine(coroutine, self.stack_trace()));
+                    return Err(VmError::AccessMissingCoroutine(coroutine, self.error_context()));
                 },
                 Op::CoSwap(a, b) if a < self.current.coroutines.len() && b < self.current.coroutines.len() => {
                     self.current.coroutines.swap(a, b);
                     self.current.ip += 1;
                 },
                 Op::CoSwap(a, b) if b < self.current.coroutines.len() => {
-                    return Err(VmError::AccessMissingCoroutine(a, self.stack_trace()));
+                    return Err(VmError::AccessMissingCoroutine(a, self.error_context()));
                 },
                 Op::CoSwap(_, b) => {
-                    return Err(VmError::AccessMissingCoroutine(b, self.stack_trace()));
+                    return Err(VmError::AccessMissingCoroutine(b, self.error_context()));
                 },
                 Op::Drop(local) if local < self.current.locals.len() => {
                     self.current.locals.remove(local);
                     self.current.ip += 1;
                 },
                 Op::Drop(local) => {
-                    return Err(VmError::AccessMissingLocal(local, self.stack_trace()));
+                    return Err(VmError::AccessMissingLocal(local, self.error_context()));
                 },
                 Op::Dup(local) if local < self.current.locals.len() => {
-                    let target = self.current.locals[local].clone();
+                    let target = self.clone_value(&self.current.locals[local])?;
                     self.current.locals.push(target);
                     self.current.ip += 1;
                 },
                 Op::Dup(local) => {
-                    return Err(VmError::AccessMissingLocal(local, self.stack_trace()));
+                    return Err(VmError::AccessMissingLocal(local, self.error_context()));
                 },
                 Op::Swap(a, b) if a < self.current.locals.len() && b < self.current.locals.len() => {
                     self.current.locals.swap(a, b);
                     self.current.ip += 1;
                 },
                 Op::Swap(a, b) if b < self.current.locals.len() => {
-                    return Err(VmError::AccessMissingLocal(a, self.stack_trace()));
+                    return Err(VmError::AccessMissingLocal(a, self.error_context()));
                 },
                 Op::Swap(_, b) => {
-                    return Err(VmError::AccessMissingLocal(b, self.stack_trace()));
+                    return Err(VmError::AccessMissingLocal(b, self.error_context()));
                 },
                 Op::PushRet if self.current.ret.is_some() => {
-                    let ret = std::mem::replace(&mut self.current.ret, None);
+                    let ret = self.current.ret.take();
                     self.current.locals.push(ret.unwrap());
                     self.current.ret = None;
                     self.current.ip += 1;
                 },
                 Op::PushRet => {
-                    return Err(VmError::AccessMissingReturn(self.stack_trace()));
+                    return Err(VmError::AccessMissingReturn(self.error_context()));
                 },
                 Op::PushLocal(ref t) => {
-                    self.current.locals.push(t.clone());
+                    let t = self.clone_value(t)?;
+                    self.current.locals.push(t);
+                    self.current.ip += 1;
+                },
+                // Note:  Store moves the top local into the slot, so any earlier value in the
+                // slot is dropped.
+                Op::Store(slot) if slot < self.current.slots.len() => {
+                    match self.current.locals.pop() {
+                        Some(v) => { self.current.slots[slot] = Some(v); },
+                        None => { return Err(VmError::AccessMissingLocal(0, self.error_context())); },
+                    }
+                    self.current.ip += 1;
+                },
+                Op::Load(slot) if slot < self.current.slots.len() => {
+                    match &self.current.slots[slot] {
+                        Some(v) => { 
+                            let v = self.clone_value(v)?;
+                            self.current.locals.push(v);
+                        },
+                        None => { return Err(VmError::AccessEmptySlot(slot, self.error_context())); },
+                    }
                     self.current.ip += 1;
+                },
+                Op::Store(slot) | Op::Load(slot) => {
+                    return Err(VmError::AccessMissingSlot(slot, self.error_context()));
+                },
+                Op::LoadGlobal(_) | Op::StoreGlobal(_) | Op::LoadGlobalSym(_) | Op::StoreGlobalSym(_) if self.global_conv.is_none() => {
+                    return Err(VmError::GlobalsNotEnabled(self.error_context()));
+                },
+                Op::LoadGlobal(global) => {
+                    self.load_global(global)?;
+                    self.current.ip += 1;
+                },
+                Op::StoreGlobal(global) => {
+                    self.store_global(global)?;
+                    self.current.ip += 1;
+                },
+                Op::LoadGlobalSym(ref name) => {
+                    let global = self.global_sym(name)?;
+                    self.load_global(global)?;
+                    self.current.ip += 1;
+                },
+                Op::StoreGlobalSym(ref name) => {
+                    let global = self.global_sym(name)?;
+                    self.store_global(global)?;
+                    self.current.ip += 1;
+                },
+            }
+        }
+    }
+
+    fn drop_coroutine(&mut self, coroutine : Coroutine<T>) {
+        if let Coroutine::Active(frame) = coroutine {
+            if let Some(hook) = self.drop_hook {
+                hook(&mut self.globals, &frame);
+            }
+            self.drop_coroutines(frame.coroutines);
+        }
+    }
+
+    // Note:  Called with the coroutines of any frame that is discarded, so that every
+    // suspended coroutine reaches the drop hook.  The frame of a finished run is kept for
+    // inspection until the next run replaces it.  An exported coroutine belongs to its
+    // handle and reaches the hook once it is released with CoRelease.
+    fn drop_coroutines(&mut self, coroutines : Vec<Coroutine<T>>) {
+        for coroutine in coroutines {
+            self.drop_coroutine(coroutine);
+        }
+    }
+
+    fn handle_at(&self, local : usize) -> Result<Handle, VmError> {
+        let from_value = match &self.handle_conv {
+            Some(conv) => conv.from_value,
+            None => { return Err(VmError::HandlesNotEnabled(self.error_context())); },
+        };
+        match self.current.locals.get(local).map(from_value) {
+            Some(Some(handle)) => Ok(handle),
+            Some(None) => Err(VmError::LocalIsNotHandle(local, self.error_context())),
+            None => Err(VmError::AccessMissingLocal(local, self.error_context())),
+        }
+    }
+
+    fn channel_at(&self, local : usize) -> Result<usize, VmError> {
+        match self.handle_at(local)? {
+            Handle::Channel(channel) if channel < self.channels.len() => Ok(channel),
+            Handle::Channel(channel) => Err(VmError::AccessMissingHandle(channel, self.error_context())),
+            _ => Err(VmError::LocalIsNotHandle(local, self.error_context())),
+        }
+    }
+
+    // Note:  Checks that argc params can enter the function.  Functions without an arity
+    // take any params.  The move call ops check this before taking anything out of the
+    // caller, so a failed call leaves the caller's locals as they were.
+    fn check_params(&self, fun_id : usize, argc : usize) -> Result<(), VmError> {
+        let fun = self.funs.get(fun_id).map(|versions| latest(versions));
+        if let Some(FunDef { fun: Fun { name, .. }, arity: Some(arity), .. }) = fun {
+            let max = arity.required + arity.defaults.len();
+            if argc < arity.required {
+                return Err(VmError::ArityMismatch(Rc::clone(name), arity.required, argc, self.error_context()));
+            }
+            if argc > max && !arity.rest {
+                return Err(VmError::ArityMismatch(Rc::clone(name), max, argc, self.error_context()));
+            }
+            if argc < max {
+                self.clone_fn()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Note:  Fills in any defaults that were not given.
+    fn enter_frame(&self, fun_id : usize, mut locals : Vec<T>) -> Result<Frame<T>, VmError> {
+        let argc = locals.len();
+        self.check_params(fun_id, argc)?;
+        if let Some(FunDef { arity: Some(arity), .. }) = self.funs.get(fun_id).map(|versions| latest(versions)) {
+            let max = arity.required + arity.defaults.len();
+            if argc < max {
+                for default in &arity.defaults[argc - arity.required..] {
+                    locals.push(self.clone_value(default)?);
                 }
             }
         }
+        let mut frame = self.new_frame(fun_id, locals);
+        frame.argc = argc;
+        Ok(frame)
+    }
+
+    // Note:  Every run starts from a fresh entry frame with no params.  The frames left
+    // behind by an earlier run or error are discarded along with their coroutines.
+    fn enter_entry(&mut self, entry : usize) -> Result<(), VmError> {
+        if entry >= self.funs.len() {
+            return Err(VmError::FunDoesNotExist(entry, vec![].into()));
+        }
+        let frames = std::mem::take(&mut self.frames);
+        let frame = self.new_frame(entry, vec![]);
+        let previous = std::mem::replace(&mut self.current, frame);
+        for frame in frames.into_iter().chain(std::iter::once(previous)) {
+            self.drop_coroutines(frame.coroutines);
+        }
+        self.current = self.enter_frame(entry, vec![])?;
+        Ok(())
+    }
+
+    fn global_sym(&self, name : &Rc<str>) -> Result<usize, VmError> {
+        self.global_index(name).ok_or_else(|| VmError::GlobalDoesNotExist(Rc::clone(name), self.error_context()))
+    }
+
+    fn load_global(&mut self, global : usize) -> Result<(), VmError> {
+        let load = self.global_conv.as_ref().unwrap().load;
+        match self.globals.get(global) {
+            Some(value) => {
+                self.current.locals.push(load(value));
+                Ok(())
+            },
+            None => Err(VmError::AccessMissingGlobal(global, self.error_context())),
+        }
+    }
+
+    // Note:  Moves the top local into the global.
+    fn store_global(&mut self, global : usize) -> Result<(), VmError> {
+        let store = self.global_conv.as_ref().unwrap().store;
+        if global >= self.globals.len() {
+            return Err(VmError::AccessMissingGlobal(global, self.error_context()));
+        }
+        match self.current.locals.pop() {
+            Some(value) => {
+                self.globals[global] = store(value);
+                Ok(())
+            },
+            None => Err(VmError::AccessMissingLocal(0, self.error_context())),
+        }
+    }
+
+    fn clone_fn(&self) -> Result<fn(&T) -> T, VmError> {
+        self.clone.ok_or_else(|| VmError::CloneNotEnabled(self.error_context()))
+    }
+
+    fn clone_value(&self, value : &T) -> Result<T, VmError> {
+        Ok(self.clone_fn()?(value))
+    }
+
+    fn new_frame(&self, fun_id : usize, locals : Vec<T>) -> Frame<T> {
+        let slots = self.funs.get(fun_id).map_or(0, |versions| latest(versions).slots);
+        Frame::new(fun_id, self.latest_version(fun_id), slots, locals)
+    }
+
+    fn latest_version(&self, fun_id : usize) -> usize {
+        self.funs.get(fun_id).map_or(0, |versions| versions.len() - 1)
+    }
+
+    fn error_context(&self) -> ErrorContext {
+        let op = self.funs.get(self.current.fun_id)
+            .and_then(|versions| versions.get(self.current.fun_version))
+            .and_then(|def| def.fun.instrs.get(self.current.ip));
+        let (op, locals) = match &self.debug {
+            Some(debug) => (op.map(debug.op), Some(self.current.locals.iter().map(debug.value).collect())),
+            None => (op.map(|op| op.name().to_string()), None),
+        };
+        ErrorContext { trace: self.stack_trace(), op, locals }
     }
 
     fn stack_trace(&self) -> StackTrace {
-        struct RetAddr { fun : usize, instr : usize }
+        struct RetAddr { fun : usize, version : usize, instr : usize }
 
-        let mut stack = self.frames.iter().map(|x| RetAddr { fun: x.fun_id, instr: x.ip }).collect::<Vec<_>>();
-        stack.push(RetAddr { fun: self.current.fun_id, instr: self.current.ip + 1});
+        let mut stack = self.frames.iter().map(|x| RetAddr { fun: x.fun_id, version: x.fun_version, instr: x.ip }).collect::<Vec<_>>();
+        stack.push(RetAddr { fun: self.current.fun_id, version: self.current.fun_version, instr: self.current.ip + 1});
 
         let mut trace = vec![];
         for addr in stack {
-            // Note:  if the function was already pushed into the stack, then
-            // that means that it already resolved to a known function.  Don't
-            // have to check again that the fun map has it.
-            let name = Rc::clone(&self.funs[addr.fun].name);
+            // Note:  Calls check their target before pushing a frame, but a trace is
+            // still built for whatever is on the stack, so a missing fun gets a placeholder.
+            let name = match self.funs.get(addr.fun).and_then(|versions| versions.get(addr.version)) {
+                Some(def) => Rc::clone(&def.fun.name),
+                None => "<missing>".into(),
+            };
             trace.push((name, addr.instr - 1));
         }
         trace
     }
 }
 
-fn get_local<T : Clone>(index: usize, locals : Cow<Vec<T>>) -> Result<T, Box<dyn Fn(StackTrace) -> VmError>> {
+fn get_local<T>(index: usize, locals : &[T], clone : Option<fn(&T) -> T>) -> Result<T, Box<dyn Fn(ErrorContext) -> VmError>> {
     if index >= locals.len() {
         Err(Box::new(move |trace| VmError::AccessMissingLocal(index, trace)))
     }
     else {
-        match locals {
-            Cow::Borrowed(locals) => Ok(locals[index].clone()),
-            Cow::Owned(mut locals) => Ok(locals.swap_remove(index)),
+        match clone {
+            Some(clone) => Ok(clone(&locals[index])),
+            None => Err(Box::new(VmError::CloneNotEnabled)),
         }
     }
 }
 
-fn co_is_running<T>(coroutine : &Coroutine<T>) -> bool {
-    match coroutine { 
-        Coroutine::Running => true,
-        _ => false,
+// Note:  Takes every local so the rest are dropped, but leaves them in place on error
+// so that the error context can still show them.
+fn take_local<T>(index: usize, locals : &mut Vec<T>) -> Result<T, Box<dyn Fn(ErrorContext) -> VmError>> {
+    if index >= locals.len() {
+        Err(Box::new(move |trace| VmError::AccessMissingLocal(index, trace)))
     }
+    else {
+        Ok(std::mem::take(locals).swap_remove(index))
+    }
+}
+
+fn empty_frame<T>() -> Frame<T> {
+    Frame::new(0, 0, 0, vec![])
+}
+
+// Note:  Nothing is moved unless every param is in range and appears only once.
+fn move_locals<T>(locals : &mut Vec<T>, params : &[usize]) -> Result<Vec<T>, Box<dyn Fn(ErrorContext) -> VmError>> {
+    for (i, param) in params.iter().enumerate() {
+        let param = *param;
+        if param >= locals.len() {
+            return Err(Box::new(move |trace| VmError::AccessMissingLocal(param, trace)));
+        }
+        if params[..i].contains(&param) {
+            return Err(Box::new(move |trace| VmError::DuplicateMoveParam(param, trace)));
+        }
+    }
+    let mut slots = std::mem::take(locals).into_iter().map(Some).collect::<Vec<_>>();
+    let moved = params.iter().map(|param| slots[*param].take().unwrap()).collect();
+    *locals = slots.into_iter().flatten().collect();
+    Ok(moved)
+}
+
+// Note:  The checks a function must pass before it can be loaded, either at construction
+// or through replace_fun.
+fn check_fun<T, S>(fun : &Fun<T>, ops : &GenOpRegistry<T, S>) -> Result<(), VmError> {
+    fun.verify()?;
+    for (ip, instr) in fun.instrs.iter().enumerate() {
+        if let Op::Gen(op_index, params) = instr {
+            match ops.get(*op_index) {
+                Some(GenOpEntry { op, arity: Some(arity), .. }) if *arity != params.len() => {
+                    return Err(VmError::GenOpArityMismatch(Rc::clone(op.name()), *arity, params.len(), vec![(Rc::clone(&fun.name), ip)].into()));
+                },
+                Some(_) => { },
+                None => {
+                    return Err(VmError::GenOpDoesNotExist(*op_index, vec![(Rc::clone(&fun.name), ip)].into()));
+                },
+            }
+        }
+    }
+    Ok(())
+}
+
+fn latest<T>(versions : &[FunDef<T>]) -> &FunDef<T> {
+    // Note:  A function index is only ever created with at least one version.
+    &versions[versions.len() - 1]
+}
+
+fn count_stale_frames<T>(frame : &Frame<T>, fun_id : usize, latest_version : usize) -> usize {
+    let stale = if frame.fun_id == fun_id && frame.fun_version != latest_version { 1 } else { 0 };
+    stale + count_stale_coroutines(&frame.coroutines, fun_id, latest_version)
+}
+
+fn count_stale_coroutines<'a, T : 'a>(coroutines : impl IntoIterator<Item = &'a Coroutine<T>>, fun_id : usize, latest_version : usize) -> usize {
+    coroutines.into_iter().map(|coroutine| match coroutine {
+        Coroutine::Active(frame) => count_stale_frames(frame, fun_id, latest_version),
+        _ => 0,
+    }).sum()
+}
+
+fn co_is_running<T>(coroutine : &Coroutine<T>) -> bool {
+    matches!(coroutine, Coroutine::Running)
 }
\ No newline at end of file
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::data::*;
+use crate::error::*;
+use crate::registry::*;
+
+pub struct Module<T, S> {
+    pub name : Rc<str>,
+    pub funs : Vec<FunDef<T>>,
+    pub ops : GenOpRegistry<T, S>,
+    pub exports : Vec<Rc<str>>,
+    pub imports : Vec<Rc<str>>,
+    pub globals : Vec<Rc<str>>,
+}
+
+pub struct Program<T, S> {
+    pub funs : Vec<FunDef<T>>,
+    pub ops : GenOpRegistry<T, S>,
+    pub globals : Vec<Rc<str>>,
+}
+
+pub struct Linker<T, S> {
+    modules : Vec<Module<T, S>>,
+}
+
+impl<T, S> Default for Linker<T, S> {
+    fn default() -> Self {
+        Linker { modules: vec![] }
+    }
+}
+
+impl<T, S> Linker<T, S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn module(mut self, module : Module<T, S>) -> Self {
+        self.modules.push(module);
+        self
+    }
+
+    // Note:  Within a module Call, CoSpawn, TaskSpawn and Gen indices are relative to that module's own
+    // funs and ops, so they are shifted by the module's offset in the final program.
+    // Branch targets are relative to the function they occur in and are left alone.
+    // DynCall targets are computed at runtime and cannot be relocated.  Globals are
+    // shared by name, so a global index is relative to the module's declared globals.
+    pub fn link(self) -> Result<Program<T, S>, LinkError> {
+        let mut fun_offsets = vec![];
+        let mut op_offsets = vec![];
+        let mut fun_total = 0;
+        let mut op_total = 0;
+        for module in &self.modules {
+            fun_offsets.push(fun_total);
+            op_offsets.push(op_total);
+            fun_total += module.funs.len();
+            op_total += module.ops.len();
+        }
+
+        let mut exports : HashMap<Rc<str>, usize> = HashMap::new();
+        for (m, module) in self.modules.iter().enumerate() {
+            for export in &module.exports {
+                let index = match module.funs.iter().position(|def| def.fun.name == *export) {
+                    Some(index) => index + fun_offsets[m],
+                    None => { return Err(LinkError::UnresolvedSymbol(Rc::clone(export), Rc::clone(&module.name))); },
+                };
+                if exports.insert(Rc::clone(export), index).is_some() {
+                    return Err(LinkError::DuplicateSymbol(Rc::clone(export)));
+                }
+            }
+        }
+
+        let mut globals : Vec<Rc<str>> = vec![];
+        let mut global_offsets = vec![];
+        for module in &self.modules {
+            let mut offsets = vec![];
+            for global in &module.globals {
+                match globals.iter().position(|g| g == global) {
+                    Some(index) => { offsets.push(index); },
+                    None => {
+                        offsets.push(globals.len());
+                        globals.push(Rc::clone(global));
+                    },
+                }
+            }
+            global_offsets.push(offsets);
+        }
+
+        for module in &self.modules {
+            for import in &module.imports {
+                if !exports.contains_key(import) {
+                    return Err(LinkError::UnresolvedSymbol(Rc::clone(import), Rc::clone(&module.name)));
+                }
+            }
+        }
+
+        let mut funs = Vec::with_capacity(fun_total);
+        let mut ops = GenOpRegistry::new();
+        for (m, module) in self.modules.into_iter().enumerate() {
+            let locals = module.funs.iter().enumerate().map(|(i, def)| (Rc::clone(&def.fun.name), i + fun_offsets[m])).collect::<HashMap<_, _>>();
+
+            for mut def in module.funs {
+                let fun = &mut def.fun;
+                for instr in fun.instrs.iter_mut() {
+                    match instr {
+                        Op::Call(fun_index, _) | Op::CallMove(fun_index, _) | Op::CoSpawn(fun_index, _) | Op::TaskSpawn(fun_index, _) => { *fun_index += fun_offsets[m]; },
+                        Op::Gen(op_index, _) => { *op_index += op_offsets[m]; },
+                        Op::LoadGlobal(global) | Op::StoreGlobal(global) => {
+                            match global_offsets[m].get(*global) {
+                                Some(index) => { *global = *index; },
+                                None => { return Err(LinkError::UndeclaredGlobal(*global, Rc::clone(&fun.name))); },
+                            }
+                        },
+                        // Note:  Like index access, a named global has to be declared by the module
+                        // that uses it, even when another module declares it.
+                        Op::LoadGlobalSym(name) | Op::StoreGlobalSym(name) => {
+                            let index = match module.globals.iter().position(|g| g == name) {
+                                Some(index) => global_offsets[m][index],
+                                None => { return Err(LinkError::UnresolvedSymbol(Rc::clone(name), Rc::clone(&fun.name))); },
+                            };
+                            *instr = match instr {
+                                Op::LoadGlobalSym(_) => Op::LoadGlobal(index),
+                                _ => Op::StoreGlobal(index),
+                            };
+                        },
+                        Op::CallSym(name, params) => {
+                            let index = match locals.get(name) {
+                                Some(index) => *index,
+                                None if module.imports.contains(name) => exports[name],
+                                None => { return Err(LinkError::UnresolvedSymbol(Rc::clone(name), Rc::clone(&fun.name))); },
+                            };
+                            *instr = Op::Call(index, std::mem::take(params));
+                        },
+                        _ => { },
+                    }
+                }
+                funs.push(def);
+            }
+            ops.append(module.ops);
+        }
+
+        Ok(Program { funs, ops, globals })
+    }
+}
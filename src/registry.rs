@@ -0,0 +1,78 @@
+use std::rc::Rc;
+
+use crate::data::*;
+use crate::error::*;
+
+pub struct GenOpEntry<T, S> {
+    pub op : GenOp<T, S>,
+    pub arity : Option<usize>,
+    pub description : Rc<str>,
+}
+
+pub struct GenOpRegistry<T, S> {
+    entries : Vec<GenOpEntry<T, S>>,
+}
+
+impl<T, S> Default for GenOpRegistry<T, S> {
+    fn default() -> Self {
+        GenOpRegistry { entries: vec![] }
+    }
+}
+
+impl<T, S> GenOpRegistry<T, S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(self, op : GenOp<T, S>, arity : usize, description : &str) -> Result<Self, RegistryError> {
+        self.add(GenOpEntry { op, arity: Some(arity), description: description.into() })
+    }
+
+    pub fn register_variadic(self, op : GenOp<T, S>, description : &str) -> Result<Self, RegistryError> {
+        self.add(GenOpEntry { op, arity: None, description: description.into() })
+    }
+
+    // Note:  Ops are looked up by name, so a name can only be registered once.
+    fn add(mut self, entry : GenOpEntry<T, S>) -> Result<Self, RegistryError> {
+        if self.index_of(entry.op.name()).is_some() {
+            return Err(RegistryError::DuplicateGenOp(Rc::clone(entry.op.name())));
+        }
+        self.entries.push(entry);
+        Ok(self)
+    }
+
+    // Note:  The linker relocates Gen indices rather than going by name, so modules may
+    // each bring their own op of the same name.
+    pub(crate) fn append(&mut self, other : GenOpRegistry<T, S>) {
+        self.entries.extend(other.entries);
+    }
+
+    pub fn index_of(&self, name : &str) -> Option<usize> {
+        self.entries.iter().position(|entry| &**entry.op.name() == name)
+    }
+
+    pub fn get(&self, index : usize) -> Option<&GenOpEntry<T, S>> {
+        self.entries.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[GenOpEntry<T, S>] {
+        &self.entries
+    }
+}
+
+// Note:  A plain list of GenOps carries no arity information, so none of them
+// will be arity checked.
+impl<T, S> From<Vec<GenOp<T, S>>> for GenOpRegistry<T, S> {
+    fn from(ops : Vec<GenOp<T, S>>) -> Self {
+        let entries = ops.into_iter().map(|op| GenOpEntry { op, arity: None, description: "".into() }).collect();
+        GenOpRegistry { entries }
+    }
+}
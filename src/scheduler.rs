@@ -0,0 +1,165 @@
+use crate::*;
+use crate::data::*;
+use crate::error::*;
+
+pub(crate) struct Task<T> {
+    pub(crate) frames : Vec<Frame<T>>,
+    pub(crate) current : Frame<T>,
+    pub(crate) state : TaskState<T>,
+}
+
+pub(crate) enum TaskState<T> {
+    Ready,
+    Waiting(usize),
+    Joining(Handle),
+    Receiving(Handle),
+    Finished(Option<T>),
+    Failed,
+}
+
+pub enum TaskOutcome<T> {
+    Finished(Option<T>),
+    // Note:  The task returned a value but without a clone fn it can only be moved
+    // once, so it stays with the task for TaskJoin.
+    Held,
+    Failed(VmError),
+    Waiting(usize),
+    Joining(Handle),
+    Receiving(Handle),
+}
+
+pub(crate) enum Join<T> {
+    Ready(Option<T>),
+    Pending,
+    Failed,
+    Missing,
+}
+
+impl<T, S> Vm<T, S> {
+    pub fn spawn_task(&mut self, fun : usize, params : Vec<T>) -> Result<Handle, VmError> {
+        let current = self.enter_frame(fun, params)?;
+        self.tasks.push(Task { frames: vec![], current, state: TaskState::Ready });
+        Ok(Handle::Task(self.tasks.len() - 1))
+    }
+
+    // Note:  Wakes every task that is waiting on the event.
+    pub fn signal(&mut self, event : usize) {
+        for task in self.tasks.iter_mut() {
+            if matches!(task.state, TaskState::Waiting(e) if e == event) {
+                task.state = TaskState::Ready;
+            }
+        }
+    }
+
+    // Note:  Runs tasks round robin until none of them are ready.  Tasks that complete
+    // during this call are reported with their result and tasks that are still blocked
+    // are reported with what they are blocked on.
+    pub fn run_scheduler(&mut self) -> Vec<(Handle, TaskOutcome<T>)> {
+        let saved_frames = std::mem::take(&mut self.frames);
+        let saved_current = std::mem::replace(&mut self.current, empty_frame());
+        self.in_scheduler = true;
+
+        let mut outcomes = vec![];
+        let mut next = 0;
+        while let Some(index) = self.next_ready_task(next) {
+            next = index + 1;
+
+            let task_frames = std::mem::take(&mut self.tasks[index].frames);
+            let task_current = std::mem::replace(&mut self.tasks[index].current, empty_frame());
+            self.frames = task_frames;
+            self.current = task_current;
+
+            let state = match self.execute() {
+                // Note:  Without a clone fn a returned value stays with the task for
+                // TaskJoin and the outcome reports it as held.
+                Ok(Exit::Return(v)) => {
+                    let outcome = match (self.clone, &v) {
+                        (Some(clone), v) => TaskOutcome::Finished(v.as_ref().map(clone)),
+                        (None, Some(_)) => TaskOutcome::Held,
+                        (None, None) => TaskOutcome::Finished(None),
+                    };
+                    outcomes.push((Handle::Task(index), outcome));
+                    TaskState::Finished(v)
+                },
+                Ok(Exit::Yield) => {
+                    self.current.ip += 1;
+                    TaskState::Ready
+                },
+                Ok(Exit::Wait(event)) => {
+                    self.current.ip += 1;
+                    TaskState::Waiting(event)
+                },
+                Ok(Exit::Join(handle)) => TaskState::Joining(handle),
+                Ok(Exit::Recv(handle)) => TaskState::Receiving(handle),
+                Ok(Exit::Await(name, _)) => {
+                    outcomes.push((Handle::Task(index), TaskOutcome::Failed(VmError::AsyncGenOpInSyncRun(name, self.error_context()))));
+                    TaskState::Failed
+                },
+                Err(e) => {
+                    outcomes.push((Handle::Task(index), TaskOutcome::Failed(e)));
+                    TaskState::Failed
+                },
+            };
+
+            // Note:  A finished or failed task is never resumed, so the suspended coroutines
+            // held by any of its frames are dropped and then the frames themselves.  Only the
+            // result is kept for TaskJoin.
+            if matches!(state, TaskState::Finished(_) | TaskState::Failed) {
+                let mut coroutines = vec![];
+                for frame in self.frames.iter_mut().chain(std::iter::once(&mut self.current)) {
+                    coroutines.append(&mut frame.coroutines);
+                }
+                self.drop_coroutines(coroutines);
+                self.frames = vec![];
+                self.current = empty_frame();
+            }
+
+            let task = &mut self.tasks[index];
+            task.frames = std::mem::take(&mut self.frames);
+            task.current = std::mem::replace(&mut self.current, empty_frame());
+            task.state = state;
+
+            if matches!(task.state, TaskState::Finished(_) | TaskState::Failed) {
+                for task in self.tasks.iter_mut() {
+                    if matches!(task.state, TaskState::Joining(handle) if handle == Handle::Task(index)) {
+                        task.state = TaskState::Ready;
+                    }
+                }
+            }
+        }
+
+        for (index, task) in self.tasks.iter().enumerate() {
+            match task.state {
+                TaskState::Waiting(event) => { outcomes.push((Handle::Task(index), TaskOutcome::Waiting(event))); },
+                TaskState::Joining(handle) => { outcomes.push((Handle::Task(index), TaskOutcome::Joining(handle))); },
+                TaskState::Receiving(handle) => { outcomes.push((Handle::Task(index), TaskOutcome::Receiving(handle))); },
+                _ => { },
+            }
+        }
+
+        self.frames = saved_frames;
+        self.current = saved_current;
+        self.in_scheduler = false;
+
+        outcomes
+    }
+
+    // Note:  Without a clone fn the first join moves the result out of the task.
+    pub(crate) fn join_task(&mut self, task : usize) -> Join<T> {
+        let clone = self.clone;
+        match self.tasks.get_mut(task).map(|task| &mut task.state) {
+            Some(TaskState::Finished(v)) => Join::Ready(match clone {
+                Some(clone) => v.as_ref().map(clone),
+                None => v.take(),
+            }),
+            Some(TaskState::Failed) => Join::Failed,
+            Some(_) => Join::Pending,
+            None => Join::Missing,
+        }
+    }
+
+    fn next_ready_task(&self, start : usize) -> Option<usize> {
+        let len = self.tasks.len();
+        (0..len).map(|offset| (start + offset) % len).find(|index| matches!(self.tasks[*index].state, TaskState::Ready))
+    }
+}
@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::data::*;
+use crate::error::*;
+
+pub struct SymbolTable {
+    symbols : HashMap<Rc<str>, usize>,
+}
+
+impl SymbolTable {
+    pub fn new<T>(funs : &[FunDef<T>]) -> Result<Self, LinkError> {
+        let mut symbols = HashMap::new();
+        for (index, FunDef { fun, .. }) in funs.iter().enumerate() {
+            if symbols.insert(Rc::clone(&fun.name), index).is_some() {
+                return Err(LinkError::DuplicateSymbol(Rc::clone(&fun.name)));
+            }
+        }
+        Ok(SymbolTable { symbols })
+    }
+
+    pub fn get(&self, name : &str) -> Option<usize> {
+        self.symbols.get(name).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    // Note:  Rewrites every CallSym into a Call with the index of the named function.
+    pub fn resolve<T>(&self, funs : &mut [FunDef<T>]) -> Result<(), LinkError> {
+        for FunDef { fun, .. } in funs.iter_mut() {
+            for instr in fun.instrs.iter_mut() {
+                if let Op::CallSym(name, params) = instr {
+                    let index = match self.get(name) {
+                        Some(index) => index,
+                        None => { return Err(LinkError::UnresolvedSymbol(Rc::clone(name), Rc::clone(&fun.name))); },
+                    };
+                    *instr = Op::Call(index, std::mem::take(params));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn link<T>(funs : Vec<impl Into<FunDef<T>>>) -> Result<Vec<FunDef<T>>, LinkError> {
+    let mut funs = funs.into_iter().map(Into::into).collect::<Vec<_>>();
+    let symbols = SymbolTable::new(&funs)?;
+    symbols.resolve(&mut funs)?;
+    Ok(funs)
+}
@@ -0,0 +1,132 @@
+pub mod common;
+
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+use an_a_vm::*;
+use an_a_vm::data::*;
+use an_a_vm::error::*;
+
+fn block_on<F : Future>(future : F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(v) = future.as_mut().poll(&mut cx) {
+            return v;
+        }
+    }
+}
+
+struct PendingOnce<T> {
+    value : Option<T>,
+    polled : bool,
+}
+
+impl<T : Unpin> Future for PendingOnce<T> {
+    type Output = T;
+
+    fn poll(mut self : std::pin::Pin<&mut Self>, _cx : &mut Context<'_>) -> Poll<T> {
+        if self.polled {
+            Poll::Ready(self.value.take().unwrap())
+        }
+        else {
+            self.polled = true;
+            Poll::Pending
+        }
+    }
+}
+
+fn gen_async_double<S>() -> GenOp<u8, S> {
+    GenOp::Async {
+        name: "async double".into(),
+        op: |locals, params| {
+            let v = locals[params[0]];
+            Box::pin(async move { 
+                let v = PendingOnce { value: Some(v * 2), polled: false }.await;
+                Ok(Some(v))
+            })
+        },
+    }
+}
+
+#[test]
+fn should_run_async_gen_op() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(3),
+            Op::Gen(0, vec![0]),
+            Op::PushRet,
+            Op::Gen(0, vec![1]),
+            Op::PushRet,
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![gen_async_double()]);
+
+    let data = block_on(vm.run_async(0)).unwrap().unwrap();
+
+    assert_eq!(data, 12);
+}
+
+#[test]
+fn should_run_sync_gen_ops_in_run_async() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(3),
+            Op::PushLocal(4),
+            Op::Gen(0, vec![0, 1]),
+            Op::PushRet,
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![common::gen_add()]);
+
+    let data = block_on(vm.run_async(0)).unwrap().unwrap();
+
+    assert_eq!(data, 7);
+}
+
+#[test]
+fn should_report_async_gen_op_error() {
+    let fail : GenOp<u8, u8> = GenOp::Async {
+        name: "fail".into(),
+        op: |_, _| Box::pin(async { Err("failure".into()) }),
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Gen(0, vec![]),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![fail]);
+
+    let error = block_on(vm.run_async(0));
+
+    assert!(matches!(error, Err(VmError::GenOpError(name, _, _)) if &*name == "fail"));
+}
+
+#[test]
+fn should_not_run_async_gen_op_in_sync_run() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(3),
+            Op::Gen(0, vec![0]),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![gen_async_double()]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::AsyncGenOpInSyncRun(name, context)) if &*name == "async double" && context.trace == vec![("main".into(), 1)]));
+}
@@ -3,6 +3,7 @@ pub mod common;
 
 use an_a_vm::*;
 use an_a_vm::data::*;
+use an_a_vm::error::*;
 
 #[test]
 fn should_branch() {
@@ -363,4 +364,251 @@ fn should_branch_on_finished_coroutine_in_resumed_coroutine_where_parent_has_act
     let data = vm.run(0).unwrap().unwrap();
 
     assert_eq!(data, 5);
-}
\ No newline at end of file
+}
+
+#[test]
+fn should_switch() {
+    const PUSH : usize = 0;
+    const SELECT : usize = 1;
+    const ADD : usize = 2;
+
+    let push_from_global = common::gen_push_global();
+    let set_select = common::gen_set_select();
+    let add = common::gen_add();
+
+    let pick = Fun { 
+        name: "pick".into(),
+        instrs: vec![
+            Op::Gen(SELECT, vec![0]),
+            Op::Switch(vec![2, 4, 6], 8),
+            Op::PushLocal(10),
+            Op::ReturnLocal(1),
+            Op::PushLocal(20),
+            Op::ReturnLocal(1),
+            Op::PushLocal(30),
+            Op::ReturnLocal(1),
+            Op::PushLocal(40),
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let main = Fun { 
+        name: "main".into(),
+        instrs: vec![
+            Op::Gen(PUSH, vec![0]),
+            Op::Gen(PUSH, vec![1]),
+            Op::Gen(PUSH, vec![2]),
+            Op::Gen(PUSH, vec![3]),
+            Op::Call(1, vec![0]),
+            Op::PushRet,
+            Op::Call(1, vec![1]),
+            Op::PushRet,
+            Op::Call(1, vec![2]),
+            Op::PushRet,
+            Op::Call(1, vec![3]),
+            Op::PushRet,
+            Op::Gen(ADD, vec![4, 5]),
+            Op::PushRet,
+            Op::Gen(ADD, vec![6, 7]),
+            Op::PushRet,
+            Op::Gen(ADD, vec![8, 9]),
+            Op::PushRet,
+            Op::ReturnLocal(10),
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new(
+        vec![main, pick], 
+        vec![push_from_global, set_select, add]);
+
+    vm.with_globals(vec![0, 1, 2, 5]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 100);
+}
+
+#[test]
+fn should_consume_select_on_switch() {
+    let set_select = common::gen_set_select();
+
+    let main : Fun<usize> = Fun { 
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(0),
+            Op::Gen(0, vec![0]),
+            Op::Switch(vec![3], 5),
+            Op::Switch(vec![5], 4),
+            Op::PushLocal(1),
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new(vec![main], vec![set_select]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 1);
+}
+
+#[test]
+fn should_switch_on_local() {
+    let pick : Fun<usize> = Fun { 
+        name: "pick".into(),
+        instrs: vec![
+            Op::SwitchLocal(0, vec![1, 3], 5),
+            Op::PushLocal(10),
+            Op::ReturnLocal(1),
+            Op::PushLocal(20),
+            Op::ReturnLocal(1),
+            Op::PushLocal(30),
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let main : Fun<usize> = Fun { 
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::PushLocal(7),
+            Op::Call(1, vec![0]),
+            Op::PushRet,
+            Op::Call(1, vec![1]),
+            Op::PushRet,
+            Op::Gen(0, vec![2, 3]),
+            Op::PushRet,
+            Op::ReturnLocal(4),
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new(vec![main, pick], vec![common::gen_add()]);
+
+    vm.enable_select();
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 50);
+}
+
+#[test]
+fn should_error_on_switch_local_without_select() {
+    let main : Fun<usize> = Fun { 
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(0),
+            Op::SwitchLocal(0, vec![2], 2),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new(vec![main], vec![]);
+
+    let error = vm.run(0).map_err(|e| e.summary());
+
+    assert_eq!(error, Err(ErrorSummary { kind: ErrorKind::SelectNotEnabled, trace: vec![("main".into(), 1)] }));
+}
+
+#[test]
+fn should_verify_switch_targets() {
+    let main : Fun<u8> = Fun { 
+        name: "main".into(),
+        instrs: vec![
+            Op::Switch(vec![1, 7], 1),
+            Op::Return,
+        ],
+    };
+
+    let vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    let error = vm.verify().map_err(|e| e.summary());
+
+    assert_eq!(error, Err(ErrorSummary { kind: ErrorKind::BranchTargetOutOfRange(7), trace: vec![("main".into(), 0)] }));
+}
+
+#[test]
+fn should_reject_out_of_range_branch_at_load() {
+    let main : Fun<u8> = Fun { 
+        name: "main".into(),
+        instrs: vec![
+            Op::Return,
+            Op::Branch(2),
+        ],
+    };
+
+    let error = Vm::<u8, u8>::try_new(vec![main], vec![]).err().map(|e| e.summary());
+
+    assert_eq!(error, Some(ErrorSummary { kind: ErrorKind::BranchTargetOutOfRange(2), trace: vec![("main".into(), 1)] }));
+}
+
+#[test]
+fn should_loop_with_jump_and_branch_false() {
+    const INC : usize = 0;
+    const SBE : usize = 1;
+
+    let inc = common::gen_inc();
+    let set_branch_on_equal = common::gen_set_branch_on_equal();
+
+    let main = Fun { 
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(0),
+            Op::PushLocal(5),
+            Op::Gen(SBE, vec![0, 1]),
+            Op::NotBranch,
+            Op::BranchFalse(10),
+            Op::Gen(INC, vec![0]),
+            Op::PushRet,
+            Op::Swap(0, 2),
+            Op::Drop(2),
+            Op::Jump(2),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![inc, set_branch_on_equal]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 5);
+}
+
+#[test]
+fn should_set_and_clear_branch() {
+    let main : Fun<u8> = Fun { 
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::PushLocal(2),
+            Op::SetBranch,
+            Op::BranchFalse(8),
+            Op::ClearBranch,
+            Op::Branch(8),
+            Op::NotBranch,
+            Op::Branch(9),
+            Op::ReturnLocal(0),
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 2);
+}
+
+#[test]
+fn should_verify_jump_targets() {
+    let main : Fun<u8> = Fun { 
+        name: "main".into(),
+        instrs: vec![
+            Op::Jump(1),
+            Op::BranchFalse(3),
+            Op::Return,
+        ],
+    };
+
+    let error = main.verify().map_err(|e| e.summary());
+
+    assert_eq!(error, Err(ErrorSummary { kind: ErrorKind::BranchTargetOutOfRange(3), trace: vec![("main".into(), 1)] }));
+}
@@ -0,0 +1,276 @@
+pub mod common;
+
+use an_a_vm::*;
+use an_a_vm::data::*;
+use an_a_vm::error::*;
+use an_a_vm::builder::*;
+
+const INC : usize = 0;
+const SBE : usize = 1;
+
+fn ops<S>() -> Vec<GenOp<u8, S>> {
+    vec![common::gen_inc(), common::gen_set_branch_on_equal()]
+}
+
+#[test]
+fn should_build_while_loop() {
+    let mut b = FunBuilder::new("main");
+    b.emit(Op::PushLocal(0))
+     .emit(Op::PushLocal(5))
+     .while_(
+        |b| { b.emit(Op::Gen(SBE, vec![0, 1])).emit(Op::NotBranch); },
+        |b, _| { 
+            b.emit(Op::Gen(INC, vec![0]))
+             .emit(Op::PushRet)
+             .emit(Op::Swap(0, 2))
+             .emit(Op::Drop(2));
+        })
+     .emit(Op::ReturnLocal(0));
+
+    let main = b.build().unwrap();
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], ops());
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 5);
+}
+
+#[test]
+fn should_break_outer_loop_from_inner_loop() {
+    let mut b = FunBuilder::new("main");
+    b.emit(Op::PushLocal(0))
+     .emit(Op::PushLocal(3))
+     .loop_(|b, outer| {
+        b.loop_(|b, inner| {
+            b.emit(Op::Gen(SBE, vec![0, 1]))
+             .break_if(outer)
+             .emit(Op::Gen(INC, vec![0]))
+             .emit(Op::PushRet)
+             .emit(Op::Swap(0, 2))
+             .emit(Op::Drop(2))
+             .break_(inner);
+        });
+     })
+     .emit(Op::ReturnLocal(0));
+
+    let main = b.build().unwrap();
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], ops());
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 3);
+}
+
+#[test]
+fn should_continue_loop() {
+    let mut b = FunBuilder::new("main");
+    b.emit(Op::PushLocal(0))
+     .emit(Op::PushLocal(4))
+     .loop_(|b, lp| {
+        b.emit(Op::Gen(INC, vec![0]))
+         .emit(Op::PushRet)
+         .emit(Op::Swap(0, 2))
+         .emit(Op::Drop(2))
+         .emit(Op::Gen(SBE, vec![0, 1]))
+         .emit(Op::NotBranch)
+         .continue_if(lp)
+         .break_(lp);
+     })
+     .emit(Op::ReturnLocal(0));
+
+    let main = b.build().unwrap();
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], ops());
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 4);
+}
+
+#[test]
+fn should_reject_locals_depth_mismatch_at_loop_exit() {
+    let mut b : FunBuilder<u8> = FunBuilder::new("main");
+    b.emit(Op::PushLocal(0))
+     .loop_(|b, lp| {
+        b.emit(Op::PushLocal(1))
+         .break_(lp);
+     })
+     .emit(Op::ReturnLocal(0));
+
+    let error = b.build();
+
+    assert!(matches!(error, Err(BuildError::LocalsDepthMismatch(fun, 2, 1, 2)) if &*fun == "main"));
+}
+
+#[test]
+fn should_reject_continue_on_block() {
+    let mut b : FunBuilder<u8> = FunBuilder::new("main");
+    b.block(|b, block| {
+        b.continue_(block);
+     })
+     .emit(Op::Return);
+
+    let error = b.build();
+
+    assert!(matches!(error, Err(BuildError::ContinueOutsideLoop(_, 0))));
+}
+
+#[test]
+fn should_reject_label_out_of_scope() {
+    let mut escaped = None;
+
+    let mut b : FunBuilder<u8> = FunBuilder::new("main");
+    b.block(|_, block| { escaped = Some(block); })
+     .break_(escaped.unwrap())
+     .emit(Op::Return);
+
+    let error = b.build();
+
+    assert!(matches!(error, Err(BuildError::LabelOutOfScope(_, 0))));
+}
+
+#[test]
+fn should_build_program_with_symbolic_locals_and_fun_refs() {
+    const MUL : usize = 0;
+    const DEC : usize = 1;
+    const BZ : usize = 2;
+
+    let mut program = ProgramBuilder::new();
+    let main = program.declare("main");
+    let fact = program.declare("fact");
+
+    let mut b = FunBuilder::new("fact");
+    let n = b.param();
+    let base = b.label();
+    b.gen_op(DEC, &[n]);
+    let m = b.push_ret();
+    b.gen_op(BZ, &[m])
+     .branch(base)
+     .call(fact, &[m]);
+    let sub = b.push_ret();
+    b.gen_op(MUL, &[n, sub]);
+    let result = b.push_ret();
+    b.return_local(result)
+     .place(base)
+     .return_local(n);
+    program.define(fact, b).unwrap();
+
+    let mut b = FunBuilder::new("main");
+    let five = b.push_local(5);
+    let unused = b.push_local(9);
+    b.drop_local(unused)
+     .call(fact, &[five]);
+    let result = b.push_ret();
+    b.return_local(result);
+    program.define(main, b).unwrap();
+
+    let funs = program.build().unwrap();
+
+    assert_eq!(&*funs[1].fun.name, "fact");
+
+    let mut vm : Vm<u8, u8> = Vm::new(funs, vec![common::gen_mul(), common::gen_dec(), common::gen_set_branch_on_zero()]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 120);
+}
+
+#[test]
+fn should_track_slots_after_drop_and_assign() {
+    let mut b : FunBuilder<u8> = FunBuilder::new("main");
+    let a = b.push_local(1);
+    let c = b.push_local(2);
+    let d = b.push_local(3);
+    b.drop_local(a);
+
+    assert_eq!(b.slot(a), None);
+    assert_eq!(b.slot(c), Some(0));
+    assert_eq!(b.slot(d), Some(1));
+
+    let e = b.push_local(4);
+    b.assign(c, e);
+
+    assert_eq!(b.slot(c), Some(0));
+    assert_eq!(b.slot(e), None);
+    assert_eq!(b.depth(), 2);
+
+    b.return_local(c);
+
+    let main = b.build().unwrap();
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 4);
+}
+
+#[test]
+fn should_reject_dropped_local() {
+    let mut b : FunBuilder<u8> = FunBuilder::new("main");
+    let a = b.push_local(1);
+    b.drop_local(a)
+     .return_local(a);
+
+    let error = b.build();
+
+    assert!(matches!(error, Err(BuildError::LocalOutOfScope(_, 2))));
+}
+
+#[test]
+fn should_reject_label_never_placed() {
+    let mut b : FunBuilder<u8> = FunBuilder::new("main");
+    let label = b.label();
+    b.jump(label)
+     .emit(Op::Return);
+
+    let error = b.build();
+
+    assert!(matches!(error, Err(BuildError::LabelNotPlaced(_, 0))));
+}
+
+#[test]
+fn should_reject_undefined_fun() {
+    let mut program : ProgramBuilder<u8> = ProgramBuilder::new();
+    program.declare("main");
+
+    let error = program.build();
+
+    assert!(matches!(error, Err(BuildError::UndefinedFun(name)) if &*name == "main"));
+}
+
+#[test]
+fn should_track_locals_moved_into_call() {
+    let mut program = ProgramBuilder::new();
+    let main = program.declare("main");
+    let double = program.declare("double");
+
+    let mut b = FunBuilder::new("double");
+    let x = b.param();
+    b.gen_op(0, &[x, x]);
+    let result = b.push_ret();
+    b.return_local(result);
+    program.define(double, b).unwrap();
+
+    let mut b = FunBuilder::new("main");
+    let a = b.push_local(3);
+    let c = b.push_local(4);
+    b.call_move(double, &[a]);
+
+    assert_eq!(b.slot(a), None);
+    assert_eq!(b.slot(c), Some(0));
+
+    let result = b.push_ret();
+    b.gen_op(0, &[c, result]);
+    let sum = b.push_ret();
+    b.return_local(sum);
+    program.define(main, b).unwrap();
+
+    let mut vm : Vm<u8, u8> = Vm::new(program.build().unwrap(), vec![common::gen_add()]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 10);
+}
@@ -3,6 +3,7 @@ pub mod common;
 
 use an_a_vm::*;
 use an_a_vm::data::*;
+use an_a_vm::error::*;
 
 #[test]
 fn should_dynamic_call() {
@@ -322,4 +323,412 @@ fn should_call_and_return() {
     let data = vm.run(0).unwrap().unwrap();
 
     assert_eq!(data, 9);
-}
\ No newline at end of file
+}
+
+#[test]
+fn should_return_multiple_locals() {
+    let div_mod = GenOp::Local {
+        name: "div mod".into(),
+        op: | locals, params | {
+            let (a, b) = (locals[params[0]], locals[params[1]]);
+            locals.push(a / b);
+            locals.push(a % b);
+            Ok(None)
+        },
+    };
+
+    let sub = GenOp::Local {
+        name: "sub".into(),
+        op: | locals, params | Ok(Some(locals[params[0]] - locals[params[1]])),
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::PushLocal(17),
+            Op::PushLocal(5),
+            Op::Call(1, vec![1, 2]),
+            Op::Gen(1, vec![3, 4]),
+            Op::PushRet,
+            Op::ReturnLocal(5),
+        ],
+    };
+
+    let div = Fun {
+        name: "div".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0, 1]),
+            Op::ReturnLocals(vec![2, 3]),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(
+        vec![main, div],
+        vec![div_mod, sub]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 1);
+}
+
+#[test]
+fn should_clone_repeated_return_local() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(3),
+            Op::Call(1, vec![0]),
+            Op::Gen(0, vec![1, 2]),
+            Op::PushRet,
+            Op::ReturnLocal(3),
+        ],
+    };
+
+    let twice : Fun<u8> = Fun {
+        name: "twice".into(),
+        instrs: vec![
+            Op::ReturnLocals(vec![0, 0]),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main, twice], vec![common::gen_add()]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 6);
+}
+
+#[test]
+fn should_return_single_local_from_top_level_return_locals() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::PushLocal(2),
+            Op::ReturnLocals(vec![1]),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    let data = vm.run(0).unwrap();
+
+    assert_eq!(data, Some(2));
+}
+
+#[test]
+fn should_error_on_top_level_multi_return() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::PushLocal(2),
+            Op::ReturnLocals(vec![0, 1]),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::TopLevelMultiReturn(2, _))));
+}
+
+#[test]
+fn should_fill_default_params() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(2),
+            Op::Call(1, vec![0]),
+            Op::PushRet,
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let scale = Fun::new("scale", vec![
+        Op::Gen(0, vec![0, 1]),
+        Op::PushRet,
+        Op::ReturnLocal(2),
+    ]).with_arity(Arity { required: 1, defaults: vec![10], rest: false });
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main.into(), scale], vec![common::gen_mul()]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 20);
+}
+
+#[test]
+fn should_keep_rest_params_and_count() {
+    let argc = GenOp::Frame {
+        name: "argc".into(),
+        op: | frame, _ | Ok(Some(frame.argc as u8)),
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::PushLocal(2),
+            Op::PushLocal(3),
+            Op::Call(1, vec![0, 1, 2]),
+            Op::PushRet,
+            Op::ReturnLocal(3),
+        ],
+    };
+
+    let count = Fun::new("count", vec![
+        Op::Gen(0, vec![]),
+        Op::PushRet,
+        Op::Gen(1, vec![2, 3]),
+        Op::PushRet,
+        Op::ReturnLocal(4),
+    ]).with_arity(Arity { required: 1, defaults: vec![], rest: true });
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main.into(), count], vec![argc, common::gen_add()]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 6);
+}
+
+#[test]
+fn should_error_on_arity_mismatch() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::PushLocal(2),
+            Op::PushLocal(3),
+            Op::Call(1, vec![0, 1, 2]),
+            Op::Return,
+        ],
+    };
+
+    let pair : FunDef<u8> = Fun::new("pair", vec![
+        Op::Return,
+    ]).with_arity(Arity { required: 1, defaults: vec![0], rest: false });
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main.into(), pair], vec![]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::ArityMismatch(name, 2, 3, context)) if &*name == "pair" && context.trace == vec![("main".into(), 3)]));
+}
+
+#[test]
+fn should_fill_default_params_of_entry() {
+    let main : FunDef<u8> = Fun::new("main", vec![
+        Op::ReturnLocal(0),
+    ]).with_arity(Arity { required: 0, defaults: vec![5], rest: false });
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 5);
+}
+
+#[test]
+fn should_run_entry_twice() {
+    let main : FunDef<u8> = Fun::new("main", vec![
+        Op::PushLocal(1),
+        Op::Return,
+    ]).with_arity(Arity { required: 0, defaults: vec![], rest: false });
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    assert_eq!(vm.run(0).unwrap(), None);
+    assert_eq!(vm.run(0).unwrap(), None);
+}
+
+#[test]
+fn should_run_after_error_in_callee() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(42),
+            Op::Call(1, vec![]),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let fail : Fun<u8> = Fun {
+        name: "fail".into(),
+        instrs: vec![
+            Op::ReturnLocal(3),
+        ],
+    };
+
+    let done : Fun<u8> = Fun {
+        name: "done".into(),
+        instrs: vec![
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main, fail, done], vec![]);
+
+    assert!(matches!(vm.run(0), Err(VmError::AccessMissingLocal(3, _))));
+    assert_eq!(vm.run(2).unwrap(), None);
+    assert_eq!(vm.call_depth(), 1);
+}
+
+#[test]
+fn should_move_params_and_compact_locals() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(2),
+            Op::PushLocal(3),
+            Op::PushLocal(5),
+            Op::PushLocal(7),
+            Op::CallMove(1, vec![3, 1]),
+            Op::PushRet,
+            // Note:  Locals are now [2, 5, 7 - 3]
+            Op::Gen(1, vec![0, 1]),
+            Op::PushRet,
+            Op::Gen(1, vec![2, 3]),
+            Op::PushRet,
+            Op::ReturnLocal(4),
+        ],
+    };
+
+    let sub = Fun {
+        name: "sub".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0, 1]),
+            Op::PushRet,
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    let sub_op = GenOp::Local {
+        name: "sub".into(),
+        op: | locals, params | Ok(Some(locals[params[0]] - locals[params[1]])),
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main, sub], vec![sub_op, common::gen_add()]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 11);
+}
+
+#[test]
+fn should_move_params_with_dyn_call() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::PushLocal(4),
+            Op::PushLocal(6),
+            Op::Gen(0, vec![0]),
+            Op::DynCallMove(vec![1, 2]),
+            Op::PushRet,
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let add = Fun {
+        name: "add".into(),
+        instrs: vec![
+            Op::Gen(1, vec![0, 1]),
+            Op::PushRet,
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new(vec![main, add], vec![common::gen_set_dyn_call(), common::gen_add()]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 10);
+}
+
+#[test]
+fn should_not_move_param_twice() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::PushLocal(2),
+            Op::CallMove(1, vec![1, 1]),
+            Op::Return,
+        ],
+    };
+
+    let other : Fun<u8> = Fun {
+        name: "other".into(),
+        instrs: vec![
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main, other], vec![]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::DuplicateMoveParam(1, _))));
+}
+
+#[test]
+fn should_check_arity_before_moving_params() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::PushLocal(2),
+            Op::CallMove(1, vec![0, 1]),
+            Op::Return,
+        ],
+    };
+
+    let one : FunDef<u8> = Fun::new("one", vec![
+        Op::Return,
+    ]).with_arity(Arity { required: 1, defaults: vec![], rest: false });
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main.into(), one], vec![]);
+
+    vm.enable_debug();
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::ArityMismatch(name, 1, 2, context)) if &*name == "one" && context.locals == Some(vec!["1".into(), "2".into()])));
+}
+
+#[test]
+fn should_expose_frames_after_error() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::Call(1, vec![0]),
+            Op::Return,
+        ],
+    };
+
+    let fail : Fun<u8> = Fun {
+        name: "fail".into(),
+        instrs: vec![
+            Op::PushLocal(2),
+            Op::Dup(5),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main, fail], vec![]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::AccessMissingLocal(5, _))));
+    assert_eq!(vm.call_depth(), 2);
+    assert_eq!(vm.current_frame().fun_id(), 1);
+    assert_eq!(vm.current_frame().ip(), 1);
+    assert_eq!(vm.current_frame().locals, vec![1, 2]);
+
+    let frames = vm.frames().map(|frame| (frame.fun_id(), frame.ip())).collect::<Vec<_>>();
+
+    assert_eq!(frames, vec![(0, 2), (1, 1)]);
+}
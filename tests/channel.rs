@@ -0,0 +1,174 @@
+pub mod common;
+
+use an_a_vm::*;
+use an_a_vm::data::*;
+use an_a_vm::error::*;
+use an_a_vm::scheduler::*;
+
+use common::Value;
+
+#[test]
+fn should_pass_messages_between_tasks() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::ChanNew,
+            Op::TaskSpawn(1, vec![0]),
+            Op::PushLocal(Value::Num(1)),
+            Op::ChanSend(0, 2),
+            Op::TaskYield,
+            Op::PushLocal(Value::Num(2)),
+            Op::ChanSend(0, 3),
+            Op::ChanClose(0),
+            Op::TaskJoin(1),
+            Op::PushRet,
+            Op::ReturnLocal(4),
+        ],
+    };
+
+    let consumer = Fun {
+        name: "consumer".into(),
+        instrs: vec![
+            Op::ChanRecv(0),
+            Op::Branch(5),
+            Op::PushRet,
+            Op::Gen(0, vec![]),
+            Op::Branch(0),
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main, consumer], vec![common::gen_set_branch()]);
+
+    vm.enable_handles();
+
+    vm.spawn_task(0, vec![]).unwrap();
+
+    let outcomes = vm.run_scheduler();
+
+    assert_eq!(outcomes.len(), 2);
+    assert!(matches!(outcomes[0], (Handle::Task(1), TaskOutcome::Finished(Some(Value::Num(2))))));
+    assert!(matches!(outcomes[1], (Handle::Task(0), TaskOutcome::Finished(Some(Value::Num(2))))));
+}
+
+#[test]
+fn should_report_task_blocked_on_channel() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::ChanNew,
+            Op::ChanRecv(0),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main], vec![]);
+
+    vm.enable_handles();
+
+    vm.spawn_task(0, vec![]).unwrap();
+
+    let outcomes = vm.run_scheduler();
+
+    assert!(matches!(outcomes[..], [(Handle::Task(0), TaskOutcome::Receiving(Handle::Channel(0)))]));
+}
+
+#[test]
+fn should_suspend_coroutine_on_empty_channel() {
+    let consumer = Fun {
+        name: "consumer".into(),
+        instrs: vec![
+            Op::ChanRecv(0),
+            Op::PushRet,
+            Op::CoYield(1),
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::ChanNew,
+            Op::Call(1, vec![0]), // suspends on the empty channel
+            Op::PushLocal(Value::Num(9)),
+            Op::ChanSend(0, 1),
+            Op::CoResume(0),      // yields 9
+            Op::PushRet,
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main, consumer], vec![]);
+
+    vm.enable_handles();
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, Value::Num(9));
+}
+
+#[test]
+fn should_branch_on_closed_channel() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::ChanNew,
+            Op::PushLocal(Value::Num(1)),
+            Op::ChanClose(0),
+            Op::ChanRecv(0),
+            Op::Branch(6),
+            Op::ReturnLocal(0),
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main], vec![]);
+
+    vm.enable_handles();
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, Value::Num(1));
+}
+
+#[test]
+fn should_not_send_on_closed_channel() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::ChanNew,
+            Op::PushLocal(Value::Num(1)),
+            Op::ChanClose(0),
+            Op::ChanSend(0, 1),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main], vec![]);
+
+    vm.enable_handles();
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::SendOnClosedChannel(0, _))));
+}
+
+#[test]
+fn should_not_receive_from_empty_channel_at_top_level() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::ChanNew,
+            Op::ChanRecv(0),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main], vec![]);
+
+    vm.enable_handles();
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::RecvOnEmptyChannel(0, _))));
+}
@@ -0,0 +1,198 @@
+
+pub mod common;
+
+use an_a_vm::*;
+use an_a_vm::data::*;
+use an_a_vm::error::*;
+use an_a_vm::scheduler::*;
+
+#[derive(Debug, PartialEq)]
+struct Owned(u8);
+
+fn gen_make() -> GenOp<Owned, u8> {
+    GenOp::Global {
+        name: "make".into(),
+        op: | globals, params | Ok(Some(Owned(globals[params[0]]))),
+    }
+}
+
+fn gen_add_owned() -> GenOp<Owned, u8> {
+    GenOp::Local {
+        name: "add".into(),
+        op: | locals, params | Ok(Some(Owned(locals[params[0]].0 + locals[params[1]].0))),
+    }
+}
+
+#[test]
+fn should_run_without_clone() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0]),
+            Op::PushRet,
+            Op::Gen(0, vec![1]),
+            Op::PushRet,
+            Op::CallMove(1, vec![0, 1]),
+            Op::PushRet,
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let add = Fun {
+        name: "add".into(),
+        instrs: vec![
+            Op::Gen(1, vec![0, 1]),
+            Op::PushRet,
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    let mut vm : Vm<Owned, u8> = Vm::new_without_clone(vec![main, add], vec![gen_make(), gen_add_owned()]);
+
+    vm.with_globals(vec![3, 4]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, Owned(7));
+}
+
+#[test]
+fn should_error_on_dup_without_clone() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0]),
+            Op::PushRet,
+            Op::Dup(0),
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let mut vm : Vm<Owned, u8> = Vm::new_without_clone(vec![main], vec![gen_make()]);
+
+    vm.with_globals(vec![3]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::CloneNotEnabled(context)) if context.trace == vec![("main".into(), 2)]));
+}
+
+#[test]
+fn should_error_on_cloning_call_without_clone() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0]),
+            Op::PushRet,
+            Op::Call(1, vec![0]),
+            Op::Return,
+        ],
+    };
+
+    let other = Fun {
+        name: "other".into(),
+        instrs: vec![
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Owned, u8> = Vm::new_without_clone(vec![main, other], vec![gen_make()]);
+
+    vm.with_globals(vec![3]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::CloneNotEnabled(_))));
+}
+
+#[test]
+fn should_move_return_locals_without_clone() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::Gen(1, vec![0, 1]),
+            Op::PushRet,
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    let pair = Fun {
+        name: "pair".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0]),
+            Op::PushRet,
+            Op::Gen(0, vec![1]),
+            Op::PushRet,
+            Op::ReturnLocals(vec![1, 0]),
+        ],
+    };
+
+    let mut vm : Vm<Owned, u8> = Vm::new_without_clone(vec![main, pair], vec![gen_make(), gen_add_owned()]);
+
+    vm.with_globals(vec![3, 4]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, Owned(7));
+}
+
+#[test]
+fn should_error_on_repeated_return_local_without_clone() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::Return,
+        ],
+    };
+
+    let pair = Fun {
+        name: "pair".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0]),
+            Op::PushRet,
+            Op::ReturnLocals(vec![0, 0]),
+        ],
+    };
+
+    let mut vm : Vm<Owned, u8> = Vm::new_without_clone(vec![main, pair], vec![gen_make()]);
+
+    vm.with_globals(vec![3]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::CloneNotEnabled(context)) if context.trace == vec![("main".into(), 0), ("pair".into(), 2)]));
+}
+
+#[test]
+fn should_report_held_task_result_without_clone() {
+    let make = Fun {
+        name: "make".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0]),
+            Op::PushRet,
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let empty = Fun {
+        name: "empty".into(),
+        instrs: vec![
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Owned, u8> = Vm::new_without_clone(vec![make, empty], vec![gen_make()]);
+
+    vm.with_globals(vec![3]);
+
+    let a = vm.spawn_task(0, vec![]).unwrap();
+    let b = vm.spawn_task(1, vec![]).unwrap();
+
+    let outcomes = vm.run_scheduler();
+
+    assert_eq!(outcomes.len(), 2);
+    assert!(matches!(outcomes[0], (h, TaskOutcome::Held) if h == a));
+    assert!(matches!(outcomes[1], (h, TaskOutcome::Finished(None)) if h == b));
+}
@@ -19,7 +19,7 @@ pub fn gen_set_branch_on_zero<S>() -> GenOp<u8, S> {
     GenOp::Frame {
         name: "bz".into(),
         op: |frame, params| { 
-            if let [s] = &params[..] {
+            if let [s] = params {
                 let v = frame.locals[*s];
                 frame.branch = v == 0;
             }
@@ -32,7 +32,7 @@ pub fn gen_push_global<T : Copy>() -> GenOp<T, T> {
     GenOp::Vm {
         name: "push global".into(),
         op: |env, params| { 
-            if let [s] = &params[..] {
+            if let [s] = params {
                 let v = env.globals[*s];
                 env.current.locals.push(v);
             }
@@ -45,7 +45,7 @@ pub fn gen_push_into_global<T : Copy>() -> GenOp<T, T> {
     GenOp::Vm {
         name: "push into global".into(),
         op: |env, params| { 
-            if let [s] = &params[..] {
+            if let [s] = params {
                 let v = env.current.locals[*s];
                 env.globals.push(v);
             }
@@ -58,7 +58,7 @@ pub fn gen_inc<S>() -> GenOp<u8, S> {
     GenOp::Local {
         name: "inc".into(),
         op: | locals, params |  { 
-            if let [s] = &params[..] {
+            if let [s] = params {
                 let a = &locals[*s];
                 Ok(Some(a + 1))
             }
@@ -73,7 +73,7 @@ pub fn gen_dec<S>() -> GenOp<u8, S> {
     GenOp::Local {
         name: "dec".into(),
         op: | locals, params |  { 
-            if let [s] = &params[..] {
+            if let [s] = params {
                 let a = &locals[*s];
                 Ok(Some(a - 1))
             }
@@ -88,7 +88,7 @@ pub fn gen_mul<T : std::ops::Mul<Output = T> + Copy, S>() -> GenOp<T, S> {
     GenOp::Local {
         name: "mul".into(),
         op: | locals, params |  { 
-            if let [s1, s2] = &params[..] {
+            if let [s1, s2] = params {
                 let a = &locals[*s1];
                 let b = &locals[*s2];
                 Ok(Some(*a * *b))
@@ -104,7 +104,7 @@ pub fn gen_add<T : std::ops::Add<Output = T> + Copy, S>() -> GenOp<T, S> {
     GenOp::Local {
         name: "add".into(),
         op: | locals, params |  { 
-            if let [s1, s2] = &params[..] {
+            if let [s1, s2] = params {
                 let a = &locals[*s1];
                 let b = &locals[*s2];
                 Ok(Some(*a + *b))
@@ -120,7 +120,7 @@ pub fn gen_unset_branch_on_equal<T : PartialEq, S>() -> GenOp<T, S> {
     GenOp::Frame {
         name: "set branch on equal".into(),
         op: | frame, params |  { 
-            if let [s1, s2] = &params[..] {
+            if let [s1, s2] = params {
                 let a = &frame.locals[*s1];
                 let b = &frame.locals[*s2];
                 frame.branch = *a != *b;
@@ -134,7 +134,7 @@ pub fn gen_set_branch_on_equal<T : PartialEq, S>() -> GenOp<T, S> {
     GenOp::Frame {
         name: "set branch on equal".into(),
         op: | frame, params |  { 
-            if let [s1, s2] = &params[..] {
+            if let [s1, s2] = params {
                 let a = &frame.locals[*s1];
                 let b = &frame.locals[*s2];
                 frame.branch = *a == *b;
@@ -148,7 +148,7 @@ pub fn gen_set_dyn_call<S>() -> GenOp<usize, S> {
     GenOp::Frame {
         name: "set dyn call".into(),
         op: |frame, params| {
-            if let [s] = &params[..] {
+            if let [s] = params {
                 let v = &frame.locals[*s];
                 frame.dyn_call = Some(*v);
             }
@@ -157,6 +157,19 @@ pub fn gen_set_dyn_call<S>() -> GenOp<usize, S> {
     }
 }
 
+pub fn gen_set_select<S>() -> GenOp<usize, S> {
+    GenOp::Frame {
+        name: "set select".into(),
+        op: |frame, params| {
+            if let [s] = params {
+                let v = &frame.locals[*s];
+                frame.select = Some(*v);
+            }
+            Ok(None)
+        },
+    }
+}
+
 pub fn gen_set_branch_on_finish<T, S>() -> GenOp<T, S> {
     GenOp::Frame {
         name: "set_branch_on_finish".into(),
@@ -166,4 +179,23 @@ pub fn gen_set_branch_on_finish<T, S>() -> GenOp<T, S> {
             Ok(None)
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Num(usize),
+    Handle(Handle),
+}
+
+impl HandleValue for Value {
+    fn from_handle(handle : Handle) -> Self {
+        Value::Handle(handle)
+    }
+
+    fn to_handle(&self) -> Option<Handle> {
+        match self {
+            Value::Handle(handle) => Some(*handle),
+            _ => None,
+        }
+    }
+}
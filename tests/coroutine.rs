@@ -3,6 +3,7 @@ pub mod common;
 
 use an_a_vm::*;
 use an_a_vm::data::*;
+use an_a_vm::error::*;
 
 #[test]
 fn should_yield() {
@@ -647,4 +648,419 @@ fn should_dup_coroutine() {
     let data = vm.run(0).unwrap().unwrap();
 
     assert_eq!(data, 6);
-}
\ No newline at end of file
+}
+
+#[test]
+fn should_send_value_into_coroutine() {
+    let add = common::gen_add();
+    let set_branch = common::gen_set_branch();
+
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::CoYield(0),
+            Op::PushRet,
+            Op::Gen(0, vec![0, 1]),
+            Op::PushRet,
+            Op::Drop(0),
+            Op::Drop(0),
+            Op::Gen(1, vec![]),
+            Op::Branch(0),
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(0),
+            Op::Call(1, vec![0]), // yields 0
+            Op::PushLocal(5),
+            Op::CoResumeWith(0, 1), // yields 5
+            Op::PushLocal(7),
+            Op::CoResumeWith(0, 2), // yields 12
+            Op::PushRet,
+            Op::ReturnLocal(3),
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new( 
+        vec![main, co],
+        vec![add, set_branch]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 12);
+}
+
+#[test]
+fn should_not_send_missing_local_into_coroutine() {
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(0),
+            Op::Call(1, vec![0]),
+            Op::CoResumeWith(0, 3),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new( 
+        vec![main, co],
+        vec![]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::AccessMissingLocal(3, _))));
+}
+
+#[test]
+fn should_spawn_coroutine_without_running_it() {
+    let add = common::gen_add();
+
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0, 1]),
+            Op::PushRet,
+            Op::CoYield(2),
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(3),
+            Op::PushLocal(4),
+            Op::CoSpawn(1, vec![0, 1]),
+            Op::CoSpawn(1, vec![1, 1]),
+            Op::CoResume(1), // yields 8
+            Op::PushRet,
+            Op::CoResume(0), // yields 7
+            Op::PushRet,
+            Op::Gen(0, vec![2, 3]),
+            Op::PushRet,
+            Op::ReturnLocal(4),
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new( 
+        vec![main, co],
+        vec![add]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 15);
+}
+
+#[test]
+fn should_report_coroutine_status_into_branch() {
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::CoSpawn(1, vec![]),
+            Op::CoResume(0), // yields 1
+            Op::PushRet,
+            Op::CoIsFinished(0),
+            Op::Branch(8),
+            Op::CoResume(0), // finishes
+            Op::CoIsFinished(0),
+            Op::Branch(10),
+            Op::PushLocal(0),
+            Op::ReturnLocal(1),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new( 
+        vec![main, co],
+        vec![]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 1);
+}
+
+#[test]
+fn should_not_spawn_missing_fun() {
+    let main : Fun<usize> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::CoSpawn(3, vec![]),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new( 
+        vec![main],
+        vec![]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::FunDoesNotExist(3, _))));
+}
+
+#[test]
+fn should_run_cleanup_on_cancel() {
+    let push_into_global = common::gen_push_into_global();
+
+    let co_count = GenOp::Frame {
+        name: "co_count".into(),
+        op: |frame, _| {
+            Ok(Some(frame.coroutines.len()))
+        },
+    };
+
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::CoOnCancel(4),
+            Op::PushLocal(1),
+            Op::CoYield(0),
+            Op::CoFinish,
+            Op::PushLocal(5),
+            Op::Gen(0, vec![1]),
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::CoCancel(0),
+            Op::Gen(1, vec![]),
+            Op::PushRet,
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new( 
+        vec![main, co],
+        vec![push_into_global, co_count]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 0);
+    assert_eq!(vm.with_globals(vec![]), vec![5]);
+}
+
+#[test]
+fn should_call_drop_hook_for_nested_coroutines() {
+    let inner = Fun {
+        name: "inner".into(),
+        instrs: vec![
+            Op::PushLocal(3),
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let outer = Fun {
+        name: "outer".into(),
+        instrs: vec![
+            Op::Call(2, vec![]),
+            Op::PushLocal(2),
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::CoDrop(0),
+            Op::PushLocal(0),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new( 
+        vec![main, outer, inner],
+        vec![]);
+
+    vm.on_coroutine_drop(|globals, frame| globals.push(frame.locals[0]));
+
+    vm.run(0).unwrap();
+
+    assert_eq!(vm.with_globals(vec![]), vec![2, 3]);
+}
+
+#[test]
+fn should_reject_out_of_range_cleanup_target() {
+    let co : Fun<usize> = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::CoOnCancel(3),
+            Op::CoFinish,
+        ],
+    };
+
+    let error = co.verify().map_err(|e| e.summary());
+
+    assert_eq!(error, Err(ErrorSummary { kind: ErrorKind::BranchTargetOutOfRange(3), trace: vec![("co".into(), 0)] }));
+}
+
+#[test]
+fn should_call_drop_hook_on_cancel_without_cleanup() {
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::PushLocal(7),
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::CoCancel(0),
+            Op::PushLocal(0),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new( 
+        vec![main, co],
+        vec![]);
+
+    vm.on_coroutine_drop(|globals, frame| globals.push(frame.locals[0]));
+
+    vm.run(0).unwrap();
+
+    assert_eq!(vm.with_globals(vec![]), vec![7]);
+}
+
+#[test]
+fn should_call_drop_hook_when_owning_frame_returns() {
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::PushLocal(4),
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let spawn = Fun {
+        name: "spawn".into(),
+        instrs: vec![
+            Op::Call(2, vec![]),
+            Op::Return,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::Call(1, vec![]),
+            Op::PushLocal(0),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new( 
+        vec![main, spawn, co],
+        vec![]);
+
+    vm.on_coroutine_drop(|globals, frame| globals.push(frame.locals[0]));
+
+    vm.run(0).unwrap();
+
+    assert_eq!(vm.with_globals(vec![]), vec![4, 4]);
+}
+
+#[test]
+fn should_call_drop_hook_for_children_of_finished_coroutine() {
+    let inner = Fun {
+        name: "inner".into(),
+        instrs: vec![
+            Op::PushLocal(5),
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let outer = Fun {
+        name: "outer".into(),
+        instrs: vec![
+            Op::Call(2, vec![]),
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::PushLocal(0),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new( 
+        vec![main, outer, inner],
+        vec![]);
+
+    vm.on_coroutine_drop(|globals, frame| globals.push(frame.locals[0]));
+
+    vm.run(0).unwrap();
+
+    assert_eq!(vm.with_globals(vec![]), vec![5]);
+}
+
+#[test]
+fn should_call_drop_hook_when_next_run_replaces_entry_frame() {
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::PushLocal(6),
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::PushLocal(0),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new( 
+        vec![main, co],
+        vec![]);
+
+    vm.on_coroutine_drop(|globals, frame| globals.push(frame.locals[0]));
+
+    vm.run(0).unwrap();
+
+    assert_eq!(vm.with_globals(vec![]), vec![]);
+
+    vm.run(0).unwrap();
+
+    assert_eq!(vm.with_globals(vec![]), vec![6]);
+}
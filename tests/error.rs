@@ -0,0 +1,173 @@
+
+pub mod common;
+
+use an_a_vm::*;
+use an_a_vm::data::*;
+use an_a_vm::error::*;
+
+#[test]
+fn should_include_op_name_in_context() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::Dup(3),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    let error = vm.run(0).unwrap_err();
+
+    assert_eq!(error.code(), 6);
+    assert_eq!(error.context().op.as_deref(), Some("Dup"));
+    assert_eq!(error.context().locals, None);
+    assert_eq!(error.context().trace, vec![("main".into(), 1)]);
+}
+
+#[test]
+fn should_include_op_and_locals_with_debug() {
+    let fail = GenOp::Local {
+        name: "fail".into(),
+        op: | _locals, _params | Err("bad input".into()),
+    };
+
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(4),
+            Op::PushLocal(5),
+            Op::Gen(0, vec![1]),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![fail]);
+
+    vm.enable_debug();
+
+    let error = vm.run(0).unwrap_err();
+
+    assert!(matches!(error, VmError::GenOpError(ref name, _, _) if &**name == "fail"));
+    assert_eq!(error.code(), 7);
+    assert_eq!(error.context().op.as_deref(), Some("Gen(0, [1])"));
+    assert_eq!(error.context().locals, Some(vec!["4".to_string(), "5".to_string()]));
+}
+
+#[test]
+fn should_include_context_in_top_level_yield() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::CoYield(0),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    let error = vm.run(0).unwrap_err();
+
+    assert!(matches!(error, VmError::TopLevelYield(1, _)));
+    assert_eq!(error.code(), 8);
+    assert_eq!(error.context().op.as_deref(), Some("CoYield"));
+    assert_eq!(error.context().trace, vec![("main".into(), 1)]);
+}
+
+#[test]
+fn should_keep_locals_when_return_local_fails() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    vm.enable_debug();
+
+    let error = vm.run(0).unwrap_err();
+
+    assert_eq!(error.context().locals, Some(vec!["1".to_string()]));
+    assert!(error.to_string().contains("locals [1]"));
+}
+
+#[test]
+fn should_compare_gen_op_error_summary() {
+    let fail = GenOp::Local {
+        name: "fail".into(),
+        op: | _locals, _params | Err("bad input".into()),
+    };
+
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Gen(0, vec![]),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![fail]);
+
+    let error = vm.run(0).map_err(|e| e.summary());
+
+    let expected = ErrorSummary { kind: ErrorKind::GenOpError("fail".into(), "bad input".into()), trace: vec![("main".into(), 0)] };
+
+    assert_eq!(error, Err(expected.clone()));
+    assert_ne!(expected, ErrorSummary { kind: ErrorKind::GenOpError("fail".into(), "other".into()), trace: vec![("main".into(), 0)] });
+}
+
+#[test]
+fn should_report_missing_call_target() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(7, vec![]),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    let error = vm.run(0).map_err(|e| e.summary());
+
+    assert_eq!(error, Err(ErrorSummary { kind: ErrorKind::FunDoesNotExist(7), trace: vec![("main".into(), 0)] }));
+}
+
+#[test]
+fn should_report_missing_dyn_call_target() {
+    let main : Fun<usize> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(7),
+            Op::Gen(0, vec![0]),
+            Op::DynCallMove(vec![]),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new(vec![main], vec![common::gen_set_dyn_call()]);
+
+    let error = vm.run(0).map_err(|e| e.summary());
+
+    assert_eq!(error, Err(ErrorSummary { kind: ErrorKind::FunDoesNotExist(7), trace: vec![("main".into(), 2)] }));
+}
+
+#[test]
+fn should_report_missing_entry() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    let error = vm.run(3).map_err(|e| e.summary());
+
+    assert_eq!(error, Err(ErrorSummary { kind: ErrorKind::FunDoesNotExist(3), trace: vec![] }));
+}
@@ -4,6 +4,7 @@ pub mod common;
 use an_a_vm::*;
 use an_a_vm::data::*;
 use an_a_vm::error::*;
+use an_a_vm::registry::*;
 
 #[test]
 fn should_modify_global() {
@@ -350,4 +351,89 @@ fn should_return_from_vm() {
     let data = vm.run(0).unwrap().unwrap();
 
     assert_eq!(data, 3);
-}
\ No newline at end of file
+}
+
+#[test]
+fn should_find_registered_gen_op_by_name() {
+    let ops : GenOpRegistry<u8, u8> = GenOpRegistry::new()
+        .register(common::gen_push_global(), 1, "push the global at the param index").unwrap()
+        .register(common::gen_add(), 2, "add two locals").unwrap();
+
+    let add = ops.index_of("add").unwrap();
+
+    assert_eq!(add, 1);
+    assert_eq!(ops.get(add).unwrap().arity, Some(2));
+    assert_eq!(&*ops.get(add).unwrap().description, "add two locals");
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0]),
+            Op::Gen(0, vec![1]),
+            Op::Gen(add, vec![0, 1]),
+            Op::PushRet,
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], ops);
+
+    vm.with_globals(vec![3, 4]);
+
+    assert_eq!(vm.gen_op_index("push global"), Some(0));
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 7);
+}
+
+#[test]
+fn should_error_on_gen_op_arity_mismatch() {
+    let ops : GenOpRegistry<u8, u8> = GenOpRegistry::new()
+        .register(common::gen_add(), 2, "add two locals").unwrap();
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::Gen(0, vec![0]),
+            Op::PushRet,
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], ops);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::GenOpArityMismatch(name, 2, 1, _)) if &*name == "add"));
+}
+
+#[test]
+fn should_reject_gen_op_arity_mismatch_at_load() {
+    let ops : GenOpRegistry<u8, u8> = GenOpRegistry::new()
+        .register(common::gen_add(), 2, "add two locals").unwrap();
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::Gen(0, vec![0, 0, 0]),
+            Op::PushRet,
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let error = Vm::try_new(vec![main], ops);
+
+    assert!(matches!(error, Err(VmError::GenOpArityMismatch(_, 2, 3, context)) if context.trace == vec![("main".into(), 1)]));
+}
+
+#[test]
+fn should_reject_duplicate_gen_op_name() {
+    let ops : Result<GenOpRegistry<u8, u8>, _> = GenOpRegistry::new()
+        .register(common::gen_add(), 2, "add two locals").unwrap()
+        .register_variadic(common::gen_add(), "add any locals");
+
+    assert!(matches!(ops, Err(RegistryError::DuplicateGenOp(name)) if &*name == "add"));
+}
@@ -0,0 +1,138 @@
+
+pub mod common;
+
+use an_a_vm::*;
+use an_a_vm::data::*;
+use an_a_vm::error::*;
+
+#[test]
+fn should_load_and_store_global_by_index() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::LoadGlobal(0),
+            Op::LoadGlobal(1),
+            Op::Gen(0, vec![0, 1]),
+            Op::PushRet,
+            Op::StoreGlobal(1),
+            Op::LoadGlobal(1),
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![common::gen_add()]);
+
+    vm.enable_globals();
+    vm.with_globals(vec![3, 4]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 7);
+    assert_eq!(vm.global(1), Some(&7));
+}
+
+#[test]
+fn should_load_and_store_global_by_name() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(9),
+            Op::StoreGlobalSym("counter".into()),
+            Op::LoadGlobalSym("counter".into()),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    vm.enable_globals();
+    vm.declare_globals(vec!["limit".into(), "counter".into()]);
+    vm.with_globals(vec![1, 2]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 9);
+    assert_eq!(vm.global_index("counter"), Some(1));
+    assert_eq!(vm.global(1), Some(&9));
+}
+
+#[test]
+fn should_write_global_from_host() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::LoadGlobal(0),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    vm.enable_globals();
+    vm.with_globals(vec![1]);
+    *vm.global_mut(0).unwrap() = 5;
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 5);
+}
+
+#[test]
+fn should_error_on_global_ops_when_not_enabled() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::LoadGlobal(0),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    vm.with_globals(vec![1]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::GlobalsNotEnabled(_))));
+}
+
+#[test]
+fn should_error_on_missing_global() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::LoadGlobalSym("missing".into()),
+            Op::LoadGlobal(3),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    vm.enable_globals();
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::GlobalDoesNotExist(name, _)) if &*name == "missing"));
+}
+
+#[test]
+fn should_access_globals_from_host() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(4),
+            Op::StoreGlobal(0),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    vm.enable_globals();
+    vm.globals_mut().push(1);
+
+    vm.run(0).unwrap();
+
+    assert_eq!(vm.globals(), &[4]);
+}
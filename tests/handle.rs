@@ -0,0 +1,244 @@
+pub mod common;
+
+use an_a_vm::*;
+use an_a_vm::data::*;
+use an_a_vm::error::*;
+
+use common::Value;
+
+#[test]
+fn should_consume_coroutine_through_handle() {
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::PushLocal(Value::Num(1)),
+            Op::PushLocal(Value::Num(2)),
+            Op::CoYield(0),
+            Op::CoYield(1),
+            Op::CoFinish,
+        ],
+    };
+
+    let make = Fun {
+        name: "make".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),   // yields 1
+            Op::CoExport(0),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let consume = Fun {
+        name: "consume".into(),
+        instrs: vec![
+            Op::CoImport(0),
+            Op::CoResume(0),       // yields 2
+            Op::PushRet,
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(2, vec![]),
+            Op::PushRet,
+            Op::Call(3, vec![0]),
+            Op::PushRet,
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main, co, make, consume], vec![]);
+
+    vm.enable_handles();
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, Value::Num(2));
+}
+
+#[test]
+fn should_not_import_handle_twice() {
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::PushLocal(Value::Num(1)),
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::CoExport(0),
+            Op::CoImport(0),
+            Op::CoImport(0),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main, co], vec![]);
+
+    vm.enable_handles();
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::AccessMissingHandle(0, _))));
+}
+
+#[test]
+fn should_not_import_non_handle() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(Value::Num(1)),
+            Op::CoImport(0),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main], vec![]);
+
+    vm.enable_handles();
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::LocalIsNotHandle(0, _))));
+}
+
+#[test]
+fn should_not_join_channel_handle() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::ChanNew,
+            Op::TaskJoin(0),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main], vec![]);
+
+    vm.enable_handles();
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::LocalIsNotHandle(0, _))));
+}
+
+#[test]
+fn should_not_import_task_handle() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::TaskSpawn(1, vec![]),
+            Op::CoImport(0),
+            Op::Return,
+        ],
+    };
+
+    let worker = Fun {
+        name: "worker".into(),
+        instrs: vec![
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main, worker], vec![]);
+
+    vm.enable_handles();
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::LocalIsNotHandle(0, _))));
+}
+
+#[test]
+fn should_not_send_on_coroutine_handle() {
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::CoSpawn(1, vec![]),
+            Op::CoExport(0),
+            Op::PushLocal(Value::Num(1)),
+            Op::ChanSend(0, 1),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main, co], vec![]);
+
+    vm.enable_handles();
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::LocalIsNotHandle(0, _))));
+}
+
+#[test]
+fn should_not_export_without_handles_enabled() {
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::CoExport(0),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main, co], vec![]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::HandlesNotEnabled(_))));
+}
+
+#[test]
+fn should_call_drop_hook_on_release() {
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::PushLocal(Value::Num(4)),
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::CoExport(0),
+            Op::CoRelease(0),
+            Op::CoImport(0),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main, co], vec![]);
+
+    vm.enable_handles();
+    vm.on_coroutine_drop(|globals, frame| globals.push(frame.locals[0]));
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::AccessMissingHandle(0, _))));
+    assert_eq!(vm.with_globals(vec![]), vec![Value::Num(4)]);
+}
@@ -0,0 +1,127 @@
+pub mod common;
+
+use an_a_vm::*;
+use an_a_vm::data::*;
+use an_a_vm::inspect::*;
+
+use common::Value;
+
+#[test]
+fn should_inspect_nested_coroutines() {
+    let inner = Fun {
+        name: "inner".into(),
+        instrs: vec![
+            Op::PushLocal(3),
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let outer = Fun {
+        name: "outer".into(),
+        instrs: vec![
+            Op::Call(2, vec![]),
+            Op::PushLocal(2),
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let done = Fun {
+        name: "done".into(),
+        instrs: vec![
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::Call(3, vec![]),
+            Op::PushLocal(0),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<usize, usize> = Vm::new( 
+        vec![main, outer, inner, done],
+        vec![]);
+
+    vm.run(0).unwrap();
+
+    let frames = vm.inspect_coroutines();
+
+    assert_eq!(frames.len(), 1);
+    assert_eq!(&*frames[0].fun, "main");
+    assert_eq!(frames[0].ip, 3);
+
+    let coroutines = &frames[0].coroutines;
+
+    assert_eq!(coroutines.len(), 2);
+
+    assert_eq!(coroutines[0].state, CoroutineState::Active);
+    assert_eq!(coroutines[0].fun.as_deref(), Some("outer"));
+    assert_eq!(coroutines[0].ip, Some(3));
+    assert_eq!(coroutines[0].locals, &[2]);
+    assert_eq!(coroutines[0].children.len(), 1);
+
+    let child = &coroutines[0].children[0];
+
+    assert_eq!(child.state, CoroutineState::Active);
+    assert_eq!(child.fun.as_deref(), Some("inner"));
+    assert_eq!(child.ip, Some(2));
+    assert_eq!(child.locals, &[3]);
+    assert!(child.children.is_empty());
+
+    assert_eq!(coroutines[1].state, CoroutineState::Finished);
+    assert_eq!(coroutines[1].fun, None);
+    assert!(coroutines[1].locals.is_empty());
+}
+
+#[test]
+fn should_inspect_exported_and_task_coroutines() {
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::PushLocal(Value::Num(1)),
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::Call(1, vec![]),
+            Op::CoExport(0),
+            Op::TaskWait(3),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main, co], vec![]);
+
+    vm.enable_handles();
+
+    let task = vm.spawn_task(0, vec![]).unwrap();
+
+    vm.run_scheduler();
+
+    let frames = vm.inspect_coroutines();
+
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].task, None);
+    assert_eq!(frames[1].task, Some(task));
+    assert_eq!(&*frames[1].fun, "main");
+    assert_eq!(frames[1].coroutines.len(), 1);
+    assert_eq!(frames[1].coroutines[0].fun.as_deref(), Some("co"));
+
+    let exported = vm.inspect_exported_coroutines();
+
+    assert_eq!(exported.len(), 1);
+    assert_eq!(exported[0].0, Handle::Coroutine(0));
+    assert_eq!(exported[0].1.state, CoroutineState::Active);
+    assert_eq!(exported[0].1.locals, &[Value::Num(1)]);
+}
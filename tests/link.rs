@@ -0,0 +1,404 @@
+pub mod common;
+
+use an_a_vm::*;
+use an_a_vm::data::*;
+use an_a_vm::error::*;
+use an_a_vm::link::*;
+use an_a_vm::registry::*;
+use an_a_vm::scheduler::*;
+
+use common::Value;
+
+fn math_module() -> Module<u8, u8> {
+    let double = Fun {
+        name: "double".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0, 0]),
+            Op::PushRet,
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let quadruple = Fun {
+        name: "quadruple".into(),
+        instrs: vec![
+            Op::Call(0, vec![0]),
+            Op::PushRet,
+            Op::Call(0, vec![1]),
+            Op::PushRet,
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    Module {
+        name: "math".into(),
+        funs: vec![double.into(), quadruple.into()],
+        ops: vec![common::gen_add()].into(),
+        exports: vec!["quadruple".into()],
+        imports: vec![],
+        globals: vec![],
+    }
+}
+
+#[test]
+fn should_link_modules() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0]),
+            Op::CallSym("quadruple".into(), vec![0]),
+            Op::PushRet,
+            Op::Gen(1, vec![]),
+            Op::Branch(6),
+            Op::ReturnLocal(0),
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let app = Module {
+        name: "app".into(),
+        funs: vec![main.into()],
+        ops: vec![common::gen_push_global(), common::gen_set_branch()].into(),
+        exports: vec![],
+        imports: vec!["quadruple".into()],
+        globals: vec![],
+    };
+
+    let program = Linker::new().module(app).module(math_module()).link().unwrap();
+
+    let mut vm : Vm<u8, u8> = Vm::new(program.funs, program.ops);
+
+    vm.with_globals(vec![3]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 12);
+}
+
+#[test]
+fn should_keep_gen_op_arity_through_link() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::Gen(0, vec![0]),
+            Op::PushRet,
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let app = Module {
+        name: "app".into(),
+        funs: vec![main.into()],
+        ops: GenOpRegistry::new().register(common::gen_add(), 2, "add two locals").unwrap(),
+        exports: vec![],
+        imports: vec![],
+        globals: vec![],
+    };
+
+    let program = Linker::new().module(app).link().unwrap();
+
+    let error = Vm::<u8, u8>::try_new(program.funs, program.ops);
+
+    assert!(matches!(error, Err(VmError::GenOpArityMismatch(name, 2, 1, _)) if &*name == "add"));
+}
+
+#[test]
+fn should_relocate_co_spawn() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0]),
+            Op::CallSym("double".into(), vec![0]),
+            Op::PushRet,
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let app = Module {
+        name: "app".into(),
+        funs: vec![main.into()],
+        ops: vec![common::gen_push_global()].into(),
+        exports: vec![],
+        imports: vec!["double".into()],
+        globals: vec![],
+    };
+
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0, 0]),
+            Op::PushRet,
+            Op::CoYield(1),
+            Op::CoFinish,
+        ],
+    };
+
+    let double = Fun {
+        name: "double".into(),
+        instrs: vec![
+            Op::CoSpawn(0, vec![0]),
+            Op::CoResume(0),
+            Op::PushRet,
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let coroutines = Module {
+        name: "coroutines".into(),
+        funs: vec![co.into(), double.into()],
+        ops: vec![common::gen_add()].into(),
+        exports: vec!["double".into()],
+        imports: vec![],
+        globals: vec![],
+    };
+
+    let program = Linker::new().module(app).module(coroutines).link().unwrap();
+
+    let mut vm : Vm<u8, u8> = Vm::new(program.funs, program.ops);
+
+    vm.with_globals(vec![3]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 6);
+}
+
+#[test]
+fn should_relocate_task_spawn() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(Value::Num(5)),
+            Op::CallSym("join_worker".into(), vec![0]),
+            Op::PushRet,
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let app = Module {
+        name: "app".into(),
+        funs: vec![main.into()],
+        ops: vec![].into(),
+        exports: vec![],
+        imports: vec!["join_worker".into()],
+        globals: vec![],
+    };
+
+    let worker = Fun {
+        name: "worker".into(),
+        instrs: vec![
+            Op::TaskYield,
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let join_worker = Fun {
+        name: "join_worker".into(),
+        instrs: vec![
+            Op::TaskSpawn(0, vec![0]),
+            Op::TaskJoin(1),
+            Op::PushRet,
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    let tasks = Module {
+        name: "tasks".into(),
+        funs: vec![worker.into(), join_worker.into()],
+        ops: vec![].into(),
+        exports: vec!["join_worker".into()],
+        imports: vec![],
+        globals: vec![],
+    };
+
+    let program = Linker::new().module(app).module(tasks).link().unwrap();
+
+    let mut vm : Vm<Value, Value> = Vm::new(program.funs, program.ops);
+
+    vm.enable_handles();
+
+    let main = vm.spawn_task(0, vec![]).unwrap();
+
+    let outcomes = vm.run_scheduler();
+
+    assert_eq!(outcomes.len(), 2);
+    assert!(matches!(outcomes[1], (h, TaskOutcome::Finished(Some(Value::Num(5)))) if h == main));
+}
+
+#[test]
+fn should_report_unresolved_import() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::CallSym("missing".into(), vec![]),
+            Op::Return,
+        ],
+    };
+
+    let app : Module<u8, u8> = Module {
+        name: "app".into(),
+        funs: vec![main.into()],
+        ops: vec![].into(),
+        exports: vec![],
+        imports: vec!["missing".into()],
+        globals: vec![],
+    };
+
+    let error = Linker::new().module(app).module(math_module()).link();
+
+    assert!(matches!(error, Err(LinkError::UnresolvedSymbol(name, module)) if &*name == "missing" && &*module == "app"));
+}
+
+#[test]
+fn should_report_undeclared_import() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::CallSym("quadruple".into(), vec![]),
+            Op::Return,
+        ],
+    };
+
+    let app : Module<u8, u8> = Module {
+        name: "app".into(),
+        funs: vec![main.into()],
+        ops: vec![].into(),
+        exports: vec![],
+        imports: vec![],
+        globals: vec![],
+    };
+
+    let error = Linker::new().module(app).module(math_module()).link();
+
+    assert!(matches!(error, Err(LinkError::UnresolvedSymbol(name, fun)) if &*name == "quadruple" && &*fun == "main"));
+}
+
+#[test]
+fn should_report_duplicate_export() {
+    let mut other = math_module();
+    other.name = "other".into();
+
+    let error = Linker::new().module(math_module()).module(other).link();
+
+    assert!(matches!(error, Err(LinkError::DuplicateSymbol(name)) if &*name == "quadruple"));
+}
+
+#[test]
+fn should_share_globals_by_name_across_modules() {
+    let bump = Fun {
+        name: "bump".into(),
+        instrs: vec![
+            Op::LoadGlobal(1),
+            Op::Gen(0, vec![0, 0]),
+            Op::PushRet,
+            Op::StoreGlobal(1),
+            Op::Return,
+        ],
+    };
+
+    let counter = Module {
+        name: "counter".into(),
+        funs: vec![bump.into()],
+        ops: vec![common::gen_add()].into(),
+        exports: vec!["bump".into()],
+        imports: vec![],
+        globals: vec!["unused".into(), "count".into()],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::CallSym("bump".into(), vec![]),
+            Op::LoadGlobalSym("count".into()),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let app = Module {
+        name: "app".into(),
+        funs: vec![main.into()],
+        ops: vec![].into(),
+        exports: vec![],
+        imports: vec!["bump".into()],
+        globals: vec!["count".into()],
+    };
+
+    let program = Linker::new().module(app).module(counter).link().unwrap();
+
+    assert_eq!(program.globals, vec!["count".into(), "unused".into()]);
+
+    let mut vm : Vm<u8, u8> = Vm::new(program.funs, program.ops);
+
+    vm.enable_globals();
+    vm.declare_globals(program.globals);
+    vm.with_globals(vec![5, 0]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 10);
+}
+
+#[test]
+fn should_reject_undeclared_global() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::LoadGlobal(0),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let app : Module<u8, u8> = Module {
+        name: "app".into(),
+        funs: vec![main.into()],
+        ops: vec![].into(),
+        exports: vec![],
+        imports: vec![],
+        globals: vec![],
+    };
+
+    let error = Linker::new().module(app).link();
+
+    assert!(matches!(error, Err(LinkError::UndeclaredGlobal(0, fun)) if &*fun == "main"));
+}
+
+#[test]
+fn should_reject_named_global_declared_by_other_module() {
+    let bump : Fun<u8> = Fun {
+        name: "bump".into(),
+        instrs: vec![
+            Op::Return,
+        ],
+    };
+
+    let counter : Module<u8, u8> = Module {
+        name: "counter".into(),
+        funs: vec![bump.into()],
+        ops: vec![].into(),
+        exports: vec![],
+        imports: vec![],
+        globals: vec!["count".into()],
+    };
+
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::LoadGlobalSym("count".into()),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let app : Module<u8, u8> = Module {
+        name: "app".into(),
+        funs: vec![main.into()],
+        ops: vec![].into(),
+        exports: vec![],
+        imports: vec![],
+        globals: vec![],
+    };
+
+    let error = Linker::new().module(counter).module(app).link();
+
+    assert!(matches!(error, Err(LinkError::UnresolvedSymbol(name, fun)) if &*name == "count" && &*fun == "main"));
+}
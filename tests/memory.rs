@@ -3,6 +3,7 @@ pub mod common;
 
 use an_a_vm::*;
 use an_a_vm::data::*;
+use an_a_vm::error::*;
 
 #[test]
 fn should_swap() {
@@ -133,4 +134,87 @@ fn should_push_local() {
     let data = vm.run(0).unwrap().unwrap();
 
     assert_eq!(data, 3);
-}
\ No newline at end of file
+}
+
+#[test]
+fn should_keep_slot_index_after_drop() {
+    let mul = common::gen_mul();
+
+    let main = Fun::new("main", vec![
+        Op::PushLocal(3),
+        Op::Store(1),
+        Op::PushLocal(9),
+        Op::PushLocal(5),
+        Op::Store(0),
+        Op::Drop(0),
+        Op::Load(0),
+        Op::Load(1),
+        Op::Gen(0, vec![0, 1]),
+        Op::PushRet,
+        Op::Store(1),
+        Op::Load(1),
+        Op::ReturnLocal(2),
+    ]).with_slots(2);
+
+    let mut vm : Vm<u8, u8> = Vm::new( 
+        vec![main],
+        vec![mul]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 15);
+}
+
+#[test]
+fn should_give_each_call_its_own_slots() {
+    let main = Fun::new("main", vec![
+        Op::PushLocal(3),
+        Op::Store(0),
+        Op::PushLocal(7),
+        Op::Call(1, vec![0]),
+        Op::Load(0),
+        Op::ReturnLocal(1),
+    ]).with_slots(1);
+
+    let other = Fun::new("other", vec![
+        Op::Store(0),
+        Op::Return,
+    ]).with_slots(1);
+
+    let mut vm : Vm<u8, u8> = Vm::new( 
+        vec![main, other],
+        vec![]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 3);
+}
+
+#[test]
+fn should_error_on_empty_slot() {
+    let main : FunDef<u8> = Fun::new("main", vec![
+        Op::Load(1),
+        Op::ReturnLocal(0),
+    ]).with_slots(2);
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::AccessEmptySlot(1, _))));
+}
+
+#[test]
+fn should_error_on_missing_slot() {
+    let main : FunDef<u8> = Fun::new("main", vec![
+        Op::PushLocal(1),
+        Op::Store(1),
+        Op::ReturnLocal(0),
+    ]).with_slots(1);
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::AccessMissingSlot(1, _))));
+}
@@ -0,0 +1,178 @@
+pub mod common;
+
+use an_a_vm::*;
+use an_a_vm::data::*;
+use an_a_vm::error::*;
+use an_a_vm::registry::*;
+
+#[test]
+fn should_call_replaced_fun() {
+    let one = Fun {
+        name: "value".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let two = Fun {
+        name: "value".into(),
+        instrs: vec![
+            Op::PushLocal(2),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::PushRet,
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main, one], vec![]);
+
+    let stale = vm.replace_fun(1, two).unwrap();
+
+    assert_eq!(stale, 0);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 2);
+}
+
+#[test]
+fn should_report_suspended_coroutine_in_old_version() {
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let new_co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::Call(1, vec![]),
+            Op::PushRet,
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main, co], vec![]);
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 1);
+
+    let stale = vm.replace_fun(1, new_co).unwrap();
+
+    assert_eq!(stale, 2);
+}
+
+#[test]
+fn should_not_replace_missing_fun() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Return,
+        ],
+    };
+
+    let other : Fun<u8> = Fun {
+        name: "other".into(),
+        instrs: vec![
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    let error = vm.replace_fun(1, other);
+
+    assert!(matches!(error, Err(VmError::FunDoesNotExist(1, _))));
+}
+
+#[test]
+fn should_not_replace_with_invalid_fun() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Return,
+        ],
+    };
+
+    let bad_branch : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Jump(2),
+            Op::Return,
+        ],
+    };
+
+    let bad_gen : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Gen(1, vec![]),
+            Op::Return,
+        ],
+    };
+
+    let bad_arity : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0]),
+            Op::Return,
+        ],
+    };
+
+    let ops : GenOpRegistry<u8, u8> = GenOpRegistry::new()
+        .register(common::gen_add(), 2, "add two locals").unwrap();
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], ops);
+
+    assert!(matches!(vm.replace_fun(0, bad_branch), Err(VmError::BranchTargetOutOfRange(2, _))));
+    assert!(matches!(vm.replace_fun(0, bad_gen), Err(VmError::GenOpDoesNotExist(1, _))));
+    assert!(matches!(vm.replace_fun(0, bad_arity), Err(VmError::GenOpArityMismatch(name, 2, 1, _)) if &*name == "add"));
+    assert_eq!(vm.run(0).unwrap(), None);
+}
+
+#[test]
+fn should_not_count_frame_of_completed_run() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(1),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let new_main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(2),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    assert_eq!(vm.run(0).unwrap(), Some(1));
+
+    let stale = vm.replace_fun(0, new_main).unwrap();
+
+    assert_eq!(stale, 0);
+    assert_eq!(vm.run(0).unwrap(), Some(2));
+}
@@ -0,0 +1,263 @@
+pub mod common;
+
+use an_a_vm::*;
+use an_a_vm::data::*;
+use an_a_vm::error::*;
+use an_a_vm::scheduler::*;
+
+use common::Value;
+
+#[test]
+fn should_interleave_tasks() {
+    const INTO_G : usize = 0;
+
+    let a = Fun {
+        name: "a".into(),
+        instrs: vec![
+            Op::PushLocal(Value::Num(1)),
+            Op::Gen(INTO_G, vec![0]),
+            Op::TaskYield,
+            Op::PushLocal(Value::Num(3)),
+            Op::Gen(INTO_G, vec![1]),
+            Op::ReturnLocal(1),
+        ],
+    };
+
+    let b = Fun {
+        name: "b".into(),
+        instrs: vec![
+            Op::PushLocal(Value::Num(2)),
+            Op::Gen(INTO_G, vec![0]),
+            Op::TaskYield,
+            Op::PushLocal(Value::Num(4)),
+            Op::Gen(INTO_G, vec![1]),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![a, b], vec![common::gen_push_into_global()]);
+
+    let a = vm.spawn_task(0, vec![]).unwrap();
+    let b = vm.spawn_task(1, vec![]).unwrap();
+
+    let outcomes = vm.run_scheduler();
+
+    assert_eq!(outcomes.len(), 2);
+    assert!(matches!(outcomes[0], (h, TaskOutcome::Finished(Some(Value::Num(3)))) if h == a));
+    assert!(matches!(outcomes[1], (h, TaskOutcome::Finished(None)) if h == b));
+
+    let globals = vm.with_globals(vec![]);
+
+    assert_eq!(globals, vec![Value::Num(1), Value::Num(2), Value::Num(3), Value::Num(4)]);
+}
+
+#[test]
+fn should_join_spawned_task() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(Value::Num(5)),
+            Op::TaskSpawn(1, vec![0]),
+            Op::TaskJoin(1),
+            Op::PushRet,
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    let worker = Fun {
+        name: "worker".into(),
+        instrs: vec![
+            Op::TaskYield,
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main, worker], vec![]);
+
+    vm.enable_handles();
+
+    let main = vm.spawn_task(0, vec![]).unwrap();
+
+    let outcomes = vm.run_scheduler();
+
+    assert_eq!(outcomes.len(), 2);
+    assert!(matches!(outcomes[0], (Handle::Task(1), TaskOutcome::Finished(Some(Value::Num(5))))));
+    assert!(matches!(outcomes[1], (h, TaskOutcome::Finished(Some(Value::Num(5)))) if h == main));
+}
+
+#[test]
+fn should_wait_for_host_event() {
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::TaskWait(7),
+            Op::PushLocal(Value::Num(1)),
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main], vec![]);
+
+    let task = vm.spawn_task(0, vec![]).unwrap();
+
+    let outcomes = vm.run_scheduler();
+
+    assert_eq!(outcomes.len(), 1);
+    assert!(matches!(outcomes[0], (h, TaskOutcome::Waiting(7)) if h == task));
+
+    vm.signal(3);
+
+    let outcomes = vm.run_scheduler();
+
+    assert!(matches!(outcomes[0], (_, TaskOutcome::Waiting(7))));
+
+    vm.signal(7);
+
+    let outcomes = vm.run_scheduler();
+
+    assert_eq!(outcomes.len(), 1);
+    assert!(matches!(outcomes[0], (h, TaskOutcome::Finished(Some(Value::Num(1)))) if h == task));
+}
+
+#[test]
+fn should_report_failed_task() {
+    let bad = Fun {
+        name: "bad".into(),
+        instrs: vec![
+            Op::ReturnLocal(3),
+        ],
+    };
+
+    let good = Fun {
+        name: "good".into(),
+        instrs: vec![
+            Op::TaskYield,
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![bad, good], vec![]);
+
+    vm.spawn_task(0, vec![]).unwrap();
+    vm.spawn_task(1, vec![]).unwrap();
+
+    let outcomes = vm.run_scheduler();
+
+    assert_eq!(outcomes.len(), 2);
+    assert!(matches!(outcomes[0], (Handle::Task(0), TaskOutcome::Failed(VmError::AccessMissingLocal(3, _)))));
+    assert!(matches!(outcomes[1], (Handle::Task(1), TaskOutcome::Finished(None))));
+}
+
+#[test]
+fn should_not_yield_outside_scheduler() {
+    let main : Fun<Value> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::TaskYield,
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main], vec![]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::TaskOpOutsideScheduler(context)) if context.trace == vec![("main".into(), 0)]));
+}
+
+#[test]
+fn should_error_on_spawn_task_arity_mismatch() {
+    let main : FunDef<Value> = Fun::new("main", vec![
+        Op::Return,
+    ]).with_arity(Arity { required: 1, defaults: vec![], rest: false });
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main], vec![]);
+
+    let error = vm.spawn_task(0, vec![]);
+
+    assert!(matches!(error, Err(VmError::ArityMismatch(name, 1, 0, _)) if &*name == "main"));
+}
+
+#[test]
+fn should_call_drop_hook_when_task_completes() {
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::PushLocal(Value::Num(3)),
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main, co], vec![]);
+
+    vm.on_coroutine_drop(|globals, frame| globals.push(frame.locals[0]));
+
+    vm.spawn_task(0, vec![]).unwrap();
+
+    let outcomes = vm.run_scheduler();
+
+    assert!(matches!(outcomes[..], [(Handle::Task(0), TaskOutcome::Finished(None))]));
+    assert_eq!(vm.with_globals(vec![]), vec![Value::Num(3)]);
+}
+
+#[test]
+fn should_call_drop_hook_when_task_fails() {
+    let co = Fun {
+        name: "co".into(),
+        instrs: vec![
+            Op::PushLocal(Value::Num(3)),
+            Op::CoYield(0),
+            Op::CoFinish,
+        ],
+    };
+
+    let main = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::Call(1, vec![]),
+            Op::ReturnLocal(5),
+        ],
+    };
+
+    let mut vm : Vm<Value, Value> = Vm::new(vec![main, co], vec![]);
+
+    vm.on_coroutine_drop(|globals, frame| globals.push(frame.locals[0]));
+
+    vm.spawn_task(0, vec![]).unwrap();
+
+    let outcomes = vm.run_scheduler();
+
+    assert!(matches!(outcomes[..], [(Handle::Task(0), TaskOutcome::Failed(VmError::AccessMissingLocal(5, _)))]));
+    assert_eq!(vm.with_globals(vec![]), vec![Value::Num(3)]);
+}
+
+#[test]
+fn should_free_frames_of_finished_task() {
+    let value = std::rc::Rc::new(1u8);
+
+    let worker = Fun {
+        name: "worker".into(),
+        instrs: vec![
+            Op::PushLocal(std::rc::Rc::clone(&value)),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<std::rc::Rc<u8>, u8> = Vm::new(vec![worker], vec![]);
+
+    let task = vm.spawn_task(0, vec![]).unwrap();
+
+    let outcomes = vm.run_scheduler();
+
+    assert!(matches!(outcomes[..], [(h, TaskOutcome::Finished(None))] if h == task));
+    assert_eq!(std::rc::Rc::strong_count(&value), 2);
+}
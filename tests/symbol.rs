@@ -0,0 +1,127 @@
+pub mod common;
+
+use an_a_vm::*;
+use an_a_vm::data::*;
+use an_a_vm::error::*;
+use an_a_vm::symbol::*;
+
+#[test]
+fn should_call_linked_symbol() {
+    let add = common::gen_add();
+
+    let add_up = Fun { 
+        name: "add_up".into(),
+        instrs: vec![
+            Op::Gen(0, vec![0, 1]),
+            Op::PushRet,
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    let main = Fun { 
+        name: "main".into(),
+        instrs: vec![
+            Op::PushLocal(3),
+            Op::PushLocal(5),
+            Op::CallSym("add_up".into(), vec![0, 1]),
+            Op::PushRet,
+            Op::ReturnLocal(2),
+        ],
+    };
+
+    let funs = link(vec![main, add_up]).unwrap();
+
+    let mut vm : Vm<u8, u8> = Vm::new(funs, vec![add]);
+
+    let entry = vm.fun_index("main").unwrap();
+    let data = vm.run(entry).unwrap().unwrap();
+
+    assert_eq!(data, 8);
+}
+
+#[test]
+fn should_find_fun_index() {
+    let main : Fun<u8> = Fun { name: "main".into(), instrs: vec![Op::Return] };
+    let other : Fun<u8> = Fun { name: "other".into(), instrs: vec![Op::Return] };
+
+    let vm : Vm<u8, u8> = Vm::new(vec![main, other], vec![]);
+
+    assert_eq!(vm.fun_index("other"), Some(1));
+    assert_eq!(vm.fun_index("missing"), None);
+}
+
+#[test]
+fn should_detect_duplicate_symbol() {
+    let a : Fun<u8> = Fun { name: "a".into(), instrs: vec![Op::Return] };
+    let b : Fun<u8> = Fun { name: "a".into(), instrs: vec![Op::Return] };
+
+    let error = SymbolTable::new(&[a.into(), b.into()]);
+
+    assert!(matches!(error, Err(LinkError::DuplicateSymbol(name)) if &*name == "a"));
+}
+
+#[test]
+fn should_detect_unresolved_symbol() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(), 
+        instrs: vec![
+            Op::CallSym("missing".into(), vec![]),
+            Op::Return,
+        ],
+    };
+
+    let error = link(vec![main]);
+
+    assert!(matches!(error, Err(LinkError::UnresolvedSymbol(name, fun)) if &*name == "missing" && &*fun == "main"));
+}
+
+#[test]
+fn should_not_run_unlinked_symbol() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(), 
+        instrs: vec![
+            Op::CallSym("main".into(), vec![]),
+            Op::Return,
+        ],
+    };
+
+    let mut vm : Vm<u8, u8> = Vm::new(vec![main], vec![]);
+
+    let error = vm.run(0);
+
+    assert!(matches!(error, Err(VmError::UnlinkedSymbol(_, _))));
+}
+
+#[test]
+fn should_reject_duplicate_fun_name_at_load() {
+    let a : Fun<u8> = Fun { name: "a".into(), instrs: vec![Op::Return] };
+    let b : Fun<u8> = Fun { name: "a".into(), instrs: vec![Op::Return] };
+
+    let error = Vm::<u8, u8>::try_new(vec![a, b], vec![]);
+
+    assert!(matches!(error, Err(VmError::DuplicateFunName(name, _)) if &*name == "a"));
+}
+
+#[test]
+fn should_link_fun_defs() {
+    let main : Fun<u8> = Fun {
+        name: "main".into(),
+        instrs: vec![
+            Op::CallSym("first".into(), vec![]),
+            Op::PushRet,
+            Op::ReturnLocal(0),
+        ],
+    };
+
+    let first : FunDef<u8> = Fun::new("first", vec![
+        Op::ReturnLocal(0),
+    ]).with_arity(Arity { required: 0, defaults: vec![9], rest: false });
+
+    let funs = link(vec![main.into(), first]).unwrap();
+
+    let mut vm : Vm<u8, u8> = Vm::try_new(funs, vec![]).unwrap();
+
+    let data = vm.run(0).unwrap().unwrap();
+
+    assert_eq!(data, 9);
+}